@@ -8,30 +8,34 @@ import Json.Encode as JE
 getTags : (Result Http.Error (List String) -> a) -> Cmd a
 getTags m =
     Http.get
-        { url = "/api/tags"
+        { url = "api/tags"
         , expect = Http.expectJson m (JD.list JD.string)
         }
 
 
 type alias Article =
     { title : String
-    , path : String
+    , id : String
+    , root : String
+    , relPath : String
     }
 
 
 articlesDecoder : JD.Decoder (List Article)
 articlesDecoder =
     JD.list
-        (JD.map2 Article
+        (JD.map4 Article
             (JD.field "title" JD.string)
-            (JD.field "path" JD.string)
+            (JD.field "id" JD.string)
+            (JD.field "root" JD.string)
+            (JD.field "rel_path" JD.string)
         )
 
 
 getArticles : (Result Http.Error (List Article) -> a) -> Cmd a
 getArticles m =
     Http.get
-        { url = "/api/articles"
+        { url = "api/articles"
         , expect = Http.expectJson m articlesDecoder
         }
 
@@ -39,7 +43,7 @@ getArticles m =
 getArticlesByTag : (Result Http.Error (List Article) -> a) -> String -> Cmd a
 getArticlesByTag m tag =
     Http.get
-        { url = "/api/tags/" ++ tag
+        { url = "api/tags/" ++ tag
         , expect = Http.expectJson m articlesDecoder
         }
 
@@ -47,7 +51,7 @@ getArticlesByTag m tag =
 postSearchTags : JE.Value -> (Result Http.Error (List Article) -> a) -> Cmd a
 postSearchTags jb m =
     Http.post
-        { url = "/api/search-by-tags"
+        { url = "api/search-by-tags"
         , body = Http.jsonBody jb
         , expect = Http.expectJson m articlesDecoder
         }
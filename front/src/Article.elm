@@ -1,7 +1,5 @@
 module Article exposing (Model, Msg(..), init, update, view)
 
-import Base64
-import Bytes.Encode
 import Html exposing (Html, div, text)
 import Html.Attributes as HA exposing (class, href)
 import Html.Events as HE exposing (onClick)
@@ -27,28 +25,21 @@ init =
     )
 
 
-getArticle : String -> Cmd Msg
-getArticle path =
-    case
-        Bytes.Encode.string path
-            |> Bytes.Encode.encode
-            |> Base64.fromBytes
-    of
-        Just artPath ->
-            Http.get
-                { url = "/api/articles/" ++ artPath
-                , expect =
-                    Http.expectString
-                        (GotArticle path)
-                }
-
-        Nothing ->
-            Cmd.none
+getArticle : String -> Path -> Cmd Msg
+getArticle ref path =
+    Http.get
+        { url = "api/articles/" ++ encodeRef ref
+        , expect =
+            Http.expectString
+                (GotArticle path)
+        }
 
 
+-- an article by its id, or by its path prefixed by its root ("notes/rust/a.md"),
+-- the relative links lead to its folder
 type Msg
-    = GetArticle String
-    | GotArticle String (Result Http.Error String)
+    = GetArticle String Path
+    | GotArticle Path (Result Http.Error String)
 
 
 view : Model -> Html Msg
@@ -135,8 +126,8 @@ docType path url =
 
     else
         LocalAsset <|
-            "/api/assets/"
-                ++ toB64
+            "api/assets/"
+                ++ encodeRef
                     (normalizeLink url)
 
 
@@ -170,7 +161,7 @@ customRenderer path =
                     OtherArticle url ->
                         Html.a
                             [ href "#"
-                            , HE.onClick (GetArticle url)
+                            , HE.onClick (GetArticle url url)
                             ]
                             content
         , image =
@@ -186,8 +177,8 @@ customRenderer path =
                     Nothing ->
                         let
                             imgPath =
-                                "/api/assets/"
-                                    ++ toB64
+                                "api/assets/"
+                                    ++ encodeRef
                                         (normalizeLink imageInfo.src)
 
                             isSVG =
@@ -260,25 +251,19 @@ normalizePath str =
            )
 
 
-toB64 : String -> String
-toB64 str =
-    case
-        Bytes.Encode.string str
-            |> Bytes.Encode.encode
-            |> Base64.fromBytes
-    of
-        Just b ->
-            b
-
-        Nothing ->
-            ""
+encodeRef : String -> String
+encodeRef str =
+    str
+        |> String.split "/"
+        |> List.map Url.percentEncode
+        |> String.join "/"
 
 
 update : Msg -> Model -> ( Model, Cmd Msg )
 update msg m =
     case msg of
-        GetArticle path ->
-            ( m, getArticle path )
+        GetArticle ref path ->
+            ( m, getArticle ref path )
 
         GotArticle path res ->
             case res of
@@ -47,8 +47,8 @@ update msg m =
     case msg of
         SearchMsg ms ->
             case ms of
-                Search.GetArticle str ->
-                    update (ArticleMsg (Article.GetArticle str)) m
+                Search.GetArticle a ->
+                    update (ArticleMsg (Article.GetArticle a.id (a.root ++ "/" ++ a.relPath))) m
 
                 _ ->
                     let
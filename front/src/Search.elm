@@ -43,7 +43,9 @@ type alias Path =
 
 type alias Article =
     { title : String
-    , path : Path
+    , id : String
+    , root : String
+    , relPath : Path
     }
 
 
@@ -154,7 +156,7 @@ type Msg
     | GotTags (Result Http.Error (List String))
     | TagClicked String
     | GotArticles (Result Http.Error (List Article))
-    | GetArticle String
+    | GetArticle Article
     | Search Query
     | ToggleVisibility
     | InputChanged String InputModif
@@ -340,7 +342,7 @@ searchDiv m =
 
                     _ ->
                         ul [] <|
-                            List.map (\t -> li [ HE.onClick (GetArticle t.path), HA.style "cursor" "pointer" ] [ text t.title ]) <|
+                            List.map (\t -> li [ HE.onClick (GetArticle t), HA.style "cursor" "pointer" ] [ text t.title ]) <|
                                 List.sortBy .title <|
                                     filterResults m.filter <|
                                         m.articles
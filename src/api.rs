@@ -1,44 +1,211 @@
+use crate::article_writer::TagEdit;
+use crate::auth::{Auth, Denied, Role};
+use crate::config::Listen;
 use crate::domain;
+use crate::file_response;
+use crate::multipart;
+use crate::site;
 use crate::storage;
+use crate::tls::{self, Https};
 use crate::uc;
 use crate::uc::Query;
 use actix_cors::Cors;
-use actix_web::{dev::Server, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::dev::{Server, Service};
+use actix_web::http::header::{
+    HeaderValue, AUTHORIZATION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, ETAG, IF_MATCH, LOCATION,
+    WWW_AUTHENTICATE, X_CONTENT_TYPE_OPTIONS,
+};
+use actix_web::http::HeaderMap;
+use actix_web::http::StatusCode;
+use actix_web::{guard, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+// a knowledge base and where it's mounted : "/name", or "" for a single one at the root
+#[derive(Clone)]
+pub struct Vault {
+    pub prefix: String,
+    pub store: Arc<storage::Store>,
+}
+
+// the server and where it listens : a port 0 is picked by the system when binding
 pub fn server(
-    address: &str,
-    store: &'static storage::Store,
+    listen: &Listen,
+    vaults: Vec<Vault>,
     dev_mode: bool,
-) -> Result<Server, std::io::Error> {
-    let addr = address.to_string();
+    auth: Auth,
+    attachments: &str,
+    cors_origins: &[String],
+    https: Option<Https>,
+) -> Result<(Server, Listen), std::io::Error> {
+    let bound = match listen {
+        Listen::Tcp(host, port) => {
+            Bound::Tcp(host.clone(), TcpListener::bind((host.as_str(), *port))?)
+        }
+        Listen::Unix(p) => Bound::Unix(p.clone()),
+    };
+    let listen = match &bound {
+        Bound::Tcp(host, listener) => Listen::Tcp(host.clone(), listener.local_addr()?.port()),
+        Bound::Unix(p) => Listen::Unix(p.clone()),
+    };
+    let origin = listen.url(https.is_some());
+    let https_port = match &listen {
+        Listen::Tcp(_, port) if https.is_some() => Some(*port),
+        _ => None,
+    };
+    let origins = cors_origins.to_vec();
+    let attachments = Attachments(attachments.to_string());
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(set_cors(&addr.clone(), dev_mode))
-            .data(store.clone())
-            .configure(static_routes)
-            .configure(back_routes)
-    })
-    .bind(address)?
-    .run();
+            .wrap(set_cors(origin.as_deref(), dev_mode, &origins))
+            .wrap_fn(move |req, srv| {
+                // the plain http port only redirects
+                let location = https_port
+                    .filter(|_| !req.app_config().secure())
+                    .map(|port| {
+                        let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+                        tls::https_url(req.connection_info().host(), port, path)
+                    });
+                let call = match location {
+                    None => Ok(srv.call(req)),
+                    Some(l) => Err(req.into_response(
+                        HttpResponse::PermanentRedirect()
+                            .header(LOCATION, l)
+                            .finish(),
+                    )),
+                };
+                async move {
+                    match call {
+                        Ok(fut) => fut.await,
+                        Err(resp) => Ok(resp),
+                    }
+                }
+            })
+            .data(auth.clone())
+            .data(attachments.clone())
+            .configure(|cfg| vault_routes(cfg, &vaults))
+    });
+
+    let server = match (bound, https) {
+        (Bound::Tcp(host, listener), Some(https)) => {
+            let server = server.listen_rustls(listener, https.config)?;
+            match https.http_port {
+                Some(http_port) => server.bind((host.as_str(), http_port))?,
+                None => server,
+            }
+        }
+        (Bound::Tcp(_, listener), None) => server.listen(listener)?,
+        (Bound::Unix(_), Some(_)) => {
+            return Err(std::io::Error::other("https isn't served on a unix socket"))
+        }
+        #[cfg(unix)]
+        (Bound::Unix(p), None) => {
+            remove_stale_socket(&p)?;
+            server.bind_uds(p)?
+        }
+        #[cfg(not(unix))]
+        (Bound::Unix(_), None) => {
+            return Err(std::io::Error::other(
+                "unix sockets aren't supported on this system",
+            ))
+        }
+    };
+    Ok((server.run(), listen))
+}
+
+// the tcp port is bound first : the url (and the allowed origin) depends on it
+enum Bound {
+    Tcp(String, TcpListener),
+    Unix(PathBuf),
+}
 
-    println!("listening on : {}", address);
-    Ok(server)
+// a socket left by a previous run is replaced, not one still served
+#[cfg(unix)]
+fn remove_stale_socket(p: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+    match std::fs::symlink_metadata(p) {
+        Ok(m) if m.file_type().is_socket() => {
+            if UnixStream::connect(p).is_ok() {
+                return Err(std::io::Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is in use", p.display()),
+                ));
+            }
+            std::fs::remove_file(p)
+        }
+        _ => Ok(()),
+    }
 }
 
-fn set_cors(address: &str, dev_mode: bool) -> Cors {
-    let bind_addr = &format!("http://{}", address)[..];
+// origins : the configured ones, allowed besides the app itself (its url)
+fn set_cors(app: Option<&str>, dev_mode: bool, origins: &[String]) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+        .allowed_headers(vec![AUTHORIZATION, CONTENT_TYPE, IF_MATCH])
+        .expose_headers(vec![ETAG, LOCATION]);
+    if let Some(app) = app {
+        cors = cors.allowed_origin(app);
+    }
     if dev_mode {
-        return Cors::default()
-            .allowed_origin("http://localhost:8000")
-            .allowed_origin(bind_addr)
-            .allowed_methods(vec!["GET", "POST"]);
+        cors = cors.allowed_origin("http://localhost:8000");
+    }
+    for o in origins {
+        cors = cors.allowed_origin(o);
+    }
+    cors
+}
+
+// each vault has its own store, the front and the api under its prefix
+fn vault_routes(cfg: &mut web::ServiceConfig, vaults: &[Vault]) {
+    for v in vaults {
+        let store = web::Data::from(v.store.clone());
+        let prefix = Prefix(v.prefix.clone());
+        if v.prefix.is_empty() {
+            cfg.app_data(store).data(prefix);
+            static_routes(cfg);
+            back_routes(cfg);
+            continue;
+        }
+
+        // the front calls the api relatively to the page
+        let slashed = format!("{}/", v.prefix);
+        cfg.service(web::resource(&v.prefix).to(move || {
+            HttpResponse::MovedPermanently()
+                .header(LOCATION, slashed.clone())
+                .finish()
+        }));
+        cfg.service(
+            web::scope(&v.prefix)
+                .app_data(store)
+                .data(prefix)
+                .configure(static_routes)
+                .configure(back_routes),
+        );
+    }
+
+    if vaults.iter().all(|v| !v.prefix.is_empty()) {
+        let links: Vec<(String, String)> = vaults
+            .iter()
+            .map(|v| {
+                (
+                    v.prefix.trim_start_matches('/').to_string(),
+                    format!("{}/", v.prefix),
+                )
+            })
+            .collect();
+        let page = site::list_page("Knowledge", None, &[("vaults", links)]);
+        cfg.service(web::resource("/").route(web::get().to(move || {
+            HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .body(page.clone())
+        })));
     }
-    Cors::default()
-        .allowed_methods(vec!["GET", "POST"])
-        .allowed_origin(bind_addr)
 }
 
 fn static_routes(cfg: &mut web::ServiceConfig) {
@@ -51,12 +218,73 @@ fn static_routes(cfg: &mut web::ServiceConfig) {
 fn back_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/api")
+            .wrap_fn(|req, srv| {
+                // every route needs the read role, the write routes check for more ;
+                // denied when no Auth was registered
+                let denied = match req.app_data::<web::Data<Auth>>() {
+                    Some(auth) => deny(req.headers(), auth, Role::Read).err(),
+                    None => Some(HttpResponse::InternalServerError().finish()),
+                };
+                let call = match denied {
+                    None => Ok(srv.call(req)),
+                    Some(resp) => Err(req.into_response(resp)),
+                };
+                async move {
+                    match call {
+                        Ok(fut) => fut.await,
+                        Err(resp) => Ok(resp),
+                    }
+                }
+            })
+            .route("/roots", web::get().to(get_all_roots))
             .route("/tags", web::get().to(get_all_tags))
             .route("/tags/{tag}", web::get().to(get_by_tag))
+            .route("/tags/{tag}/related", web::get().to(get_related_tags))
             .route("/search-by-tags", web::post().to(search_by_tag))
+            .route("/rename-tags", web::post().to(rename_tags))
             .route("/articles", web::get().to(get_all_articles))
-            .route("/articles/{path}", web::get().to(get_article_by_path))
-            .route("/assets/{path}", web::get().to(get_asset_by_path)),
+            .route("/articles", web::post().to(create_article))
+            // ids contain slashes : the tag routes are guarded by their method,
+            // so an article called "tags" is still found by the generic ones
+            .service(
+                web::resource("/articles/{id:.+}/tags")
+                    .guard(guard::Post())
+                    .route(web::post().to(add_article_tag)),
+            )
+            .service(
+                web::resource("/articles/{id:.+}/tags/{tag}")
+                    .guard(guard::Delete())
+                    .route(web::delete().to(remove_article_tag)),
+            )
+            .service(
+                web::resource("/articles/{id:.+}/html")
+                    .guard(guard::Get())
+                    .route(web::get().to(get_article_html)),
+            )
+            .service(
+                web::resource("/articles/{id:.+}/toc")
+                    .guard(guard::Get())
+                    .route(web::get().to(get_article_toc)),
+            )
+            .service(
+                web::resource("/articles/{id:.+}/links")
+                    .guard(guard::Get())
+                    .route(web::get().to(get_article_links)),
+            )
+            .service(
+                web::resource("/articles/{id:.+}/related")
+                    .guard(guard::Get())
+                    .route(web::get().to(get_related_articles)),
+            )
+            .route("/articles/{id:.+}", web::get().to(get_article_by_path))
+            .route("/articles/{id:.+}", web::put().to(replace_article))
+            .route("/assets/{id:.+}", web::get().to(get_asset_by_path))
+            .service(
+                web::resource("/assets")
+                    // the multipart envelope comes on top of the file
+                    .app_data(web::PayloadConfig::new(uc::MAX_ASSET_SIZE + 64 * 1024))
+                    .route(web::post().to(upload_asset)),
+            ),
     );
 }
 
@@ -85,43 +313,398 @@ async fn serve_static(f: StaticFile, _: ()) -> impl Responder {
 //
 // backend routes
 //
-async fn get_by_tag(store: web::Data<storage::Store>, tag: web::Path<String>) -> impl Responder {
-    HttpResponse::Ok().json(store.get_by_tag(&tag.into_inner()))
+// optional query string filter : ?root=name
+#[derive(Deserialize, Debug)]
+struct RootFilter {
+    root: Option<String>,
 }
 
-async fn get_all_articles(store: web::Data<storage::Store>) -> impl Responder {
-    HttpResponse::Ok().json(store.get_all_articles())
+async fn get_by_tag(
+    store: web::Data<storage::Store>,
+    tag: web::Path<String>,
+    filter: web::Query<RootFilter>,
+) -> impl Responder {
+    // looked for as it was indexed
+    HttpResponse::Ok().json(uc::filter_by_root(
+        store.get_by_tag(&store.normalize_tag(&tag)),
+        &filter.root,
+    ))
+}
+
+async fn get_all_articles(
+    store: web::Data<storage::Store>,
+    filter: web::Query<RootFilter>,
+) -> impl Responder {
+    HttpResponse::Ok().json(uc::filter_by_root(store.get_all_articles(), &filter.root))
 }
 
-async fn get_all_tags(store: web::Data<storage::Store>) -> impl Responder {
+async fn get_all_roots(store: web::Data<storage::Store>) -> impl Responder {
+    HttpResponse::Ok().json(store.get_all_roots())
+}
+
+// ?counts=true : the number of articles of each tag too
+#[derive(Deserialize, Debug)]
+struct TagsParams {
+    #[serde(default)]
+    counts: bool,
+}
+
+async fn get_all_tags(
+    store: web::Data<storage::Store>,
+    params: web::Query<TagsParams>,
+) -> impl Responder {
+    if params.counts {
+        return HttpResponse::Ok().json(store.tag_counts());
+    }
     HttpResponse::Ok().json(store.get_all_tags())
 }
-use actix_web::http::header::ContentType;
 
-async fn get_article_by_path(path: web::Path<String>) -> impl Responder {
-    let p = decode_path(path);
-    let resp = uc::get_article_content(&p).unwrap();
-    let mut builder = HttpResponse::Ok();
-    builder.set(ContentType::plaintext());
-    builder.body(resp)
+async fn get_related_tags(
+    store: web::Data<storage::Store>,
+    tag: web::Path<String>,
+) -> impl Responder {
+    HttpResponse::Ok().json(store.related_tags(&store.normalize_tag(&tag)))
 }
 
-async fn get_asset_by_path(path: web::Path<String>) -> impl Responder {
-    let p = decode_path(path);
-    let bin = std::fs::read(&p).unwrap();
-    HttpResponse::Ok().body(bin)
+async fn get_article_by_path(
+    req: HttpRequest,
+    store: web::Data<storage::Store>,
+    id: web::Path<String>,
+) -> impl Responder {
+    match uc::resolve(&id, &store)
+        .and_then(|p| uc::get_file(&p, &store))
+        .and_then(|p| file_response::respond(&req, &p, Some("text/plain; charset=utf-8")))
+    {
+        Ok(resp) => resp,
+        Err(e) => error_response(&e),
+    }
 }
 
-async fn search_by_tag(
+async fn get_article_html(
     store: web::Data<storage::Store>,
-    json_query: web::Json<JsonQuery>,
+    prefix: web::Data<Prefix>,
+    id: web::Path<String>,
+) -> impl Responder {
+    let api = format!("{}/api", prefix.0);
+    match uc::resolve(&id, &store).and_then(|p| uc::render_article(&p, &api, &store)) {
+        Ok(html) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            // the notes' own html is displayed, never run
+            .header(CONTENT_SECURITY_POLICY, "sandbox; script-src 'none'")
+            .body(html),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn get_article_toc(
+    store: web::Data<storage::Store>,
+    id: web::Path<String>,
+) -> impl Responder {
+    match uc::resolve(&id, &store).and_then(|p| uc::article_toc(&p, &store)) {
+        Ok(toc) => HttpResponse::Ok().json(toc),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn get_article_links(
+    store: web::Data<storage::Store>,
+    id: web::Path<String>,
+) -> impl Responder {
+    match uc::resolve(&id, &store).and_then(|p| uc::article_links(&p, &store)) {
+        Ok(links) => HttpResponse::Ok().json(links),
+        Err(e) => error_response(&e),
+    }
+}
+
+// ?k=5 : how many, 10 by default
+#[derive(Deserialize, Debug)]
+struct RelatedParams {
+    #[serde(default = "default_related")]
+    k: usize,
+}
+
+fn default_related() -> usize {
+    10
+}
+
+async fn get_related_articles(
+    store: web::Data<storage::Store>,
+    id: web::Path<String>,
+    params: web::Query<RelatedParams>,
 ) -> impl Responder {
-    HttpResponse::Ok().json(uc::search_by_tag(&JsonQuery::to_uc(&json_query), &store))
+    match uc::resolve(&id, &store).and_then(|p| uc::related_articles(&p, params.k, &store)) {
+        Ok(related) => HttpResponse::Ok().json(related),
+        Err(e) => error_response(&e),
+    }
+}
+
+//
+// write routes : they need the write role, eg: "Authorization: Bearer <write token>"
+//
+fn check_token(req: &HttpRequest, auth: &Auth) -> Result<(), HttpResponse> {
+    deny(req.headers(), auth, Role::Write)
+}
+
+fn deny(headers: &HeaderMap, auth: &Auth, needed: Role) -> Result<(), HttpResponse> {
+    let given = headers.get(AUTHORIZATION).and_then(|h| h.to_str().ok());
+    match auth.check(given, needed) {
+        Ok(()) => Ok(()),
+        Err(Denied::Unauthenticated) => Err(HttpResponse::Unauthorized()
+            .header(WWW_AUTHENTICATE, auth.challenge())
+            .finish()),
+        Err(Denied::Forbidden) if needed == Role::Write => {
+            Err(HttpResponse::Forbidden().body("writes aren't allowed"))
+        }
+        Err(Denied::Forbidden) => Err(HttpResponse::Forbidden().finish()),
+    }
 }
 
-fn decode_path(path: web::Path<String>) -> String {
-    let dec = base64::decode(&path.into_inner()).unwrap();
-    String::from_utf8(dec).unwrap()
+#[derive(Deserialize, Debug)]
+struct JsonNewArticle {
+    title: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    body: String,
+    root: Option<String>,
+    path: Option<String>,
+}
+
+async fn create_article(
+    req: HttpRequest,
+    auth: web::Data<Auth>,
+    store: web::Data<storage::Store>,
+    prefix: web::Data<Prefix>,
+    new: web::Json<JsonNewArticle>,
+) -> impl Responder {
+    if let Err(resp) = check_token(&req, &auth) {
+        return resp;
+    }
+
+    let new = new.into_inner();
+    let a = uc::NewArticle {
+        title: new.title,
+        tags: new.tags,
+        body: new.body,
+        root: new.root,
+        path: new.path,
+    };
+    match uc::create_article(&a, &store) {
+        Ok((art, etag)) => HttpResponse::Created()
+            .header(ETAG, etag)
+            .header(LOCATION, format!("{}/api/articles/{}", prefix.0, art.id))
+            .json(art),
+        Err(e) => write_error_response(e),
+    }
+}
+
+// the whole file is replaced, If-Match must hold the etag it was read with
+async fn replace_article(
+    req: HttpRequest,
+    auth: web::Data<Auth>,
+    store: web::Data<storage::Store>,
+    id: web::Path<String>,
+    content: String,
+) -> impl Responder {
+    if let Err(resp) = check_token(&req, &auth) {
+        return resp;
+    }
+
+    let if_match = match req.headers().get(IF_MATCH).and_then(|h| h.to_str().ok()) {
+        Some(m) => m.to_string(),
+        None => return HttpResponse::build(StatusCode::PRECONDITION_REQUIRED).finish(),
+    };
+    let p = match uc::resolve(&id, &store) {
+        Ok(p) => p,
+        Err(e) => return error_response(&e),
+    };
+    match uc::replace_article(&p, &content, &if_match, &store) {
+        Ok(etag) => HttpResponse::Ok().header(ETAG, etag).finish(),
+        Err(e) => write_error_response(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct JsonRenameTags {
+    from: Vec<String>,
+    to: String,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// returns the changed files
+async fn rename_tags(
+    req: HttpRequest,
+    auth: web::Data<Auth>,
+    store: web::Data<storage::Store>,
+    rename: web::Json<JsonRenameTags>,
+) -> impl Responder {
+    if let Err(resp) = check_token(&req, &auth) {
+        return resp;
+    }
+
+    match uc::rename_tags(&rename.from, &rename.to, rename.dry_run, &store) {
+        Ok(changed) => HttpResponse::Ok().json(changed),
+        Err(e) => write_error_response(uc::WriteError::Io(e)),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct JsonTag {
+    tag: String,
+}
+
+// both return the article with its new tags
+async fn add_article_tag(
+    req: HttpRequest,
+    auth: web::Data<Auth>,
+    store: web::Data<storage::Store>,
+    id: web::Path<String>,
+    tag: web::Json<JsonTag>,
+) -> impl Responder {
+    if let Err(resp) = check_token(&req, &auth) {
+        return resp;
+    }
+
+    let p = match uc::resolve(&id, &store) {
+        Ok(p) => p,
+        Err(e) => return error_response(&e),
+    };
+    match uc::edit_article_tags(&p, &TagEdit::Add(&tag.tag), &store) {
+        Ok(m) => HttpResponse::Ok().json(m),
+        Err(e) => write_error_response(e),
+    }
+}
+
+async fn remove_article_tag(
+    req: HttpRequest,
+    auth: web::Data<Auth>,
+    store: web::Data<storage::Store>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    if let Err(resp) = check_token(&req, &auth) {
+        return resp;
+    }
+
+    let (id, tag) = path.into_inner();
+    let p = match uc::resolve(&id, &store) {
+        Ok(p) => p,
+        Err(e) => return error_response(&e),
+    };
+    match uc::edit_article_tags(&p, &TagEdit::Remove(&tag), &store) {
+        Ok(m) => HttpResponse::Ok().json(m),
+        Err(e) => write_error_response(e),
+    }
+}
+
+// folder of the uploads, relative to the article
+#[derive(Clone)]
+struct Attachments(String);
+
+// where the vault is mounted, see Vault
+struct Prefix(String);
+
+// ?article=<id of the article the asset is pasted in>
+#[derive(Deserialize, Debug)]
+struct UploadTarget {
+    article: String,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonUploaded {
+    link: String,
+}
+
+// multipart form, the image is the "file" field
+async fn upload_asset(
+    req: HttpRequest,
+    auth: web::Data<Auth>,
+    attachments: web::Data<Attachments>,
+    store: web::Data<storage::Store>,
+    target: web::Query<UploadTarget>,
+    body: web::Bytes,
+) -> impl Responder {
+    if let Err(resp) = check_token(&req, &auth) {
+        return resp;
+    }
+
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let file = match multipart::parse(content_type, &body)
+        .and_then(|parts| parts.into_iter().find(|p| p.name == "file"))
+    {
+        Some(f) => f,
+        None => return HttpResponse::BadRequest().body("a multipart form with a file is expected"),
+    };
+
+    let article = match uc::resolve(&target.article, &store) {
+        Ok(p) => p,
+        Err(e) => return error_response(&e),
+    };
+    match uc::upload_asset(&article, &attachments.0, &file.data, &store) {
+        Ok(link) => HttpResponse::Created().json(JsonUploaded { link }),
+        Err(e) => write_error_response(e),
+    }
+}
+
+fn write_error_response(e: uc::WriteError) -> HttpResponse {
+    match e {
+        uc::WriteError::TooLarge => HttpResponse::PayloadTooLarge().finish(),
+        uc::WriteError::UnsupportedType => HttpResponse::UnsupportedMediaType().finish(),
+        uc::WriteError::Conflict(etag) => HttpResponse::PreconditionFailed()
+            .header(ETAG, etag)
+            .finish(),
+        uc::WriteError::Io(e) => match e.kind() {
+            ErrorKind::AlreadyExists => HttpResponse::Conflict().finish(),
+            ErrorKind::InvalidInput => HttpResponse::BadRequest().body(e.to_string()),
+            _ => error_response(&e),
+        },
+    }
+}
+
+async fn get_asset_by_path(
+    req: HttpRequest,
+    store: web::Data<storage::Store>,
+    id: web::Path<String>,
+) -> impl Responder {
+    match uc::resolve(&id, &store)
+        .and_then(|p| uc::get_file(&p, &store))
+        .and_then(|p| file_response::respond(&req, &p, None))
+    {
+        Ok(mut resp) => {
+            // svg & html assets are displayed, never run
+            let h = resp.headers_mut();
+            h.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+            h.insert(
+                CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static("sandbox; script-src 'none'"),
+            );
+            resp
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+fn error_response(e: &std::io::Error) -> HttpResponse {
+    match e.kind() {
+        ErrorKind::NotFound => HttpResponse::NotFound().finish(),
+        ErrorKind::PermissionDenied => HttpResponse::Forbidden().finish(),
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn search_by_tag(
+    store: web::Data<storage::Store>,
+    json_query: web::Json<JsonQuery>,
+    filter: web::Query<RootFilter>,
+) -> impl Responder {
+    HttpResponse::Ok().json(uc::filter_by_root(
+        uc::search_by_tag(&JsonQuery::to_uc(&json_query), &store),
+        &filter.root,
+    ))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -130,6 +713,9 @@ enum JsonQuery {
     Sing {
         val: String,
     },
+    Root {
+        val: String,
+    },
     Comb {
         op: domain::Op,
         qa: Box<JsonQuery>,
@@ -141,6 +727,7 @@ impl JsonQuery {
     fn to_uc(jq: &JsonQuery) -> Query {
         match &jq {
             JsonQuery::Sing { val } => Query::Sing(val.clone()),
+            JsonQuery::Root { val } => Query::Root(val.clone()),
             JsonQuery::Comb { op, qa, qb } => Query::Comb(
                 op.clone(),
                 Box::new(JsonQuery::to_uc(qa)),
@@ -149,3 +736,104 @@ impl JsonQuery {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use tempfile::tempdir;
+
+    #[test]
+    fn port_known_once_bound() -> std::io::Result<()> {
+        actix_web::rt::System::new("test").block_on(async {
+            let listen = Listen::Tcp("127.0.0.1".into(), 0);
+            let (server, bound) = server(&listen, vec![], false, Auth::default(), "", &[], None)?;
+            server.stop(true).await;
+            assert!(matches!(bound, Listen::Tcp(_, port) if port != 0));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn vaults_apart() -> std::io::Result<()> {
+        let vault = |prefix: &str, tag: &str| {
+            let store = storage::Store::new();
+            let path = PathBuf::from(format!("/{}/a.md", tag));
+            store.insert(&domain::TaggedArticle::new(path, "a", &[tag.into()]));
+            Vault {
+                prefix: prefix.into(),
+                store: Arc::new(store),
+            }
+        };
+        let vaults = vec![vault("/one", "rust"), vault("/two", "web")];
+
+        actix_web::rt::System::new("test").block_on(async move {
+            let mut app = test::init_service(
+                App::new()
+                    .data(Auth::default())
+                    .data(Attachments(String::new()))
+                    .configure(|cfg| vault_routes(cfg, &vaults)),
+            )
+            .await;
+            let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+            let tags: Vec<String> =
+                test::read_body_json(test::call_service(&mut app, get("/one/api/tags")).await)
+                    .await;
+            assert_eq!(vec!["rust"], tags);
+            let tags: Vec<String> =
+                test::read_body_json(test::call_service(&mut app, get("/two/api/tags")).await)
+                    .await;
+            assert_eq!(vec!["web"], tags);
+            let arts: Vec<serde_json::Value> =
+                test::read_body_json(test::call_service(&mut app, get("/one/api/tags/web")).await)
+                    .await;
+            assert!(arts.is_empty());
+
+            // the front calls the api relatively to the page
+            let resp = test::call_service(&mut app, get("/one")).await;
+            assert_eq!(StatusCode::MOVED_PERMANENTLY, resp.status());
+            assert_eq!(
+                Some(&HeaderValue::from_static("/one/")),
+                resp.headers().get(LOCATION)
+            );
+            let resp = test::call_service(&mut app, get("/")).await;
+            assert_eq!(StatusCode::OK, resp.status());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn closed_without_auth() -> std::io::Result<()> {
+        actix_web::rt::System::new("test").block_on(async move {
+            let mut app = test::init_service(
+                App::new()
+                    .data(storage::Store::new())
+                    .configure(back_routes),
+            )
+            .await;
+            let req = test::TestRequest::get().uri("/api/tags").to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+            Ok(())
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_socket() -> std::io::Result<()> {
+        use std::os::unix::net::UnixListener;
+        let dir = tempdir()?;
+        let p = dir.path().join("kb.sock");
+
+        let served = UnixListener::bind(&p)?;
+        assert_eq!(
+            ErrorKind::AddrInUse,
+            remove_stale_socket(&p).unwrap_err().kind()
+        );
+        drop(served); // the file stays
+        remove_stale_socket(&p)?;
+        assert!(!p.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,525 @@
+use crate::domain::TagRules;
+use crate::file_handler;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::UNIX_EPOCH;
+use yaml_rust::{yaml, Yaml, YamlEmitter, YamlLoader};
+
+// suffix of the temp files used for atomic writes, the watcher ignores them
+pub static TMP_SUFFIX: &str = ".knowledge.tmp";
+
+// changes whenever the file is modified
+pub fn etag(p: &Path) -> io::Result<String> {
+    let meta = fs::metadata(p)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!(
+        "\"{}.{}-{}\"",
+        mtime.as_secs(),
+        mtime.subsec_nanos(),
+        meta.len()
+    ))
+}
+
+// the content is written next to the file then moved over it,
+// readers never see a partially written file
+pub fn write_atomic(p: &Path, content: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path(p)?;
+    let res = File::create(&tmp).and_then(|mut f| {
+        f.write_all(content)?;
+        f.sync_all()
+    });
+
+    match res.and_then(|_| fs::rename(&tmp, p)) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+fn tmp_path(p: &Path) -> io::Result<PathBuf> {
+    match (p.parent(), p.file_name()) {
+        (Some(dir), Some(name)) => {
+            Ok(dir.join(format!(".{}{}", name.to_string_lossy(), TMP_SUFFIX)))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file")),
+    }
+}
+
+pub fn new_article(title: &str, tags: &[String], body: &str) -> String {
+    let mut header = yaml::Hash::new();
+    header.insert(Yaml::String("title".into()), Yaml::String(title.into()));
+    header.insert(
+        Yaml::String("tags".into()),
+        Yaml::Array(tags.iter().map(|t| Yaml::String(t.clone())).collect()),
+    );
+
+    let mut out = String::new();
+    // the emitter starts the document with the delimiter
+    let _ = YamlEmitter::new(&mut out).dump(&Yaml::Hash(header));
+    format!("{}\n---\n\n{}", out, body)
+}
+
+// file name for a title : "My cool title" -> "my-cool-title.md"
+pub fn file_name(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+
+    if slug.is_empty() {
+        return "article.md".into();
+    }
+    format!("{}.md", slug)
+}
+
+// images that can be pasted in an article, recognized by their first bytes
+static IMAGE_TYPES: &[(&str, &[u8], &str)] = &[
+    ("image/png", b"\x89PNG\r\n\x1a\n", "png"),
+    ("image/jpeg", b"\xff\xd8\xff", "jpg"),
+    ("image/gif", b"GIF8", "gif"),
+    ("image/webp", b"RIFF", "webp"), // followed by the size then "WEBP"
+];
+
+pub fn image_type(data: &[u8]) -> Option<&'static str> {
+    IMAGE_TYPES
+        .iter()
+        .find(|(mime, magic, _)| {
+            data.starts_with(magic) && (*mime != "image/webp" || data.get(8..12) == Some(b"WEBP"))
+        })
+        .map(|(mime, _, _)| *mime)
+}
+
+// named after its content : the same image uploaded twice is stored once
+pub fn asset_name(data: &[u8]) -> Option<String> {
+    let ext = IMAGE_TYPES
+        .iter()
+        .find(|(mime, _, _)| Some(*mime) == image_type(data))?
+        .2;
+    let hash: String = Sha256::digest(data)[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Some(format!("{}.{}", hash, ext))
+}
+
+pub enum TagEdit<'a> {
+    // the tags listed in `from` become `to`, merged when already there
+    Rename(&'a [String], &'a str),
+    Add(&'a str),
+    Remove(&'a str),
+}
+
+// only the tags of the header are edited, the rest of the file is kept byte
+// for byte ; None when the edit changes nothing. The header tags are matched
+// by their normalized form, as the store indexed them
+pub fn edit_tags(content: &[u8], edit: &TagEdit, rules: &TagRules) -> Option<Vec<u8>> {
+    // only the header has to be valid utf-8
+    let lines: Vec<&[u8]> = content.split_inclusive(|b| *b == b'\n').collect();
+    let is_delim = |l: &[u8]| str::from_utf8(l).is_ok_and(file_handler::is_yaml_delim);
+    if !lines.first().is_some_and(|l| is_delim(l)) {
+        return None;
+    }
+    let end = lines.iter().skip(1).position(|l| is_delim(l))? + 1;
+    // header[0] is the opening delimiter
+    let header: Vec<&str> = lines[..end]
+        .iter()
+        .map(|l| str::from_utf8(l).ok())
+        .collect::<Option<_>>()?;
+
+    let (out, rest) = match header.iter().position(|l| l.starts_with("tags:")) {
+        Some(start) if header[start]["tags:".len()..].trim().is_empty() => {
+            edit_block_list(&header, start, edit, rules)?
+        }
+        Some(start) => edit_flow_list(&header, start, edit, rules)?,
+        None => match edit {
+            TagEdit::Add(tag) => {
+                let nl = line_end(header[0]);
+                let mut out: Vec<String> = header.iter().map(|l| l.to_string()).collect();
+                out.push(format!("tags:{}- {}{}", nl, quote(tag), nl));
+                (out, end)
+            }
+            _ => return None,
+        },
+    };
+
+    let mut out = out.concat().into_bytes();
+    for l in &lines[rest..] {
+        out.extend_from_slice(l);
+    }
+    Some(out)
+}
+
+// the edited lines, up to the index of the first line left untouched
+type Edited = (Vec<String>, usize);
+
+fn normalize(rules: &TagRules, tag: &str) -> Option<String> {
+    rules.normalize(&[tag.to_string()]).pop()
+}
+
+fn same(rules: &TagRules, a: &str, b: &str) -> bool {
+    normalize(rules, a).is_some_and(|a| Some(a) == normalize(rules, b))
+}
+
+// the normalized tags to rename
+fn renamed(rules: &TagRules, edit: &TagEdit) -> Vec<String> {
+    match edit {
+        TagEdit::Rename(from, _) => rules.normalize(from),
+        _ => vec![],
+    }
+}
+
+// one "- tag" per line
+fn edit_block_list(
+    header: &[&str],
+    start: usize,
+    edit: &TagEdit,
+    rules: &TagRules,
+) -> Option<Edited> {
+    let from = renamed(rules, edit);
+    let mut out: Vec<String> = header[..=start].iter().map(|l| l.to_string()).collect();
+    let mut changed = false;
+    let mut last_item: Option<usize> = None; // in out
+    let mut rest = start + 1;
+    let mut has_to = false;
+
+    while rest < header.len() && is_list_item(header[rest]) {
+        let line = header[rest];
+        rest += 1;
+        let tag = scalar(item_value(line));
+        if tag.is_some() {
+            last_item = Some(out.len());
+        }
+
+        match edit {
+            TagEdit::Rename(_, to) => {
+                let renamed = tag
+                    .as_deref()
+                    .and_then(|t| normalize(rules, t))
+                    .is_some_and(|t| from.contains(&t));
+                if renamed || tag.as_deref().is_some_and(|t| same(rules, t, to)) {
+                    if has_to {
+                        changed |= renamed;
+                        continue; // merged
+                    }
+                    has_to = true;
+                }
+                if renamed && tag.as_deref() != Some(to) {
+                    changed = true;
+                    out.push(replace_item(line, to));
+                    continue;
+                }
+            }
+            TagEdit::Add(t) if tag.as_deref().is_some_and(|tag| same(rules, tag, t)) => {
+                return None
+            }
+            TagEdit::Remove(t) if tag.as_deref().is_some_and(|tag| same(rules, tag, t)) => {
+                changed = true;
+                continue;
+            }
+            _ => {}
+        }
+        out.push(line.into());
+    }
+
+    if let TagEdit::Add(t) = edit {
+        // after the last item, indented the same way
+        let (at, like) = match last_item {
+            Some(i) => (i + 1, out[i].clone()),
+            None => (start + 1, format!("- x{}", line_end(header[start]))),
+        };
+        out.insert(at, replace_item(&like, t));
+        changed = true;
+    }
+
+    if !changed {
+        return None;
+    }
+    Some((out, rest))
+}
+
+// tags: [a, b]
+fn edit_flow_list(
+    header: &[&str],
+    start: usize,
+    edit: &TagEdit,
+    rules: &TagRules,
+) -> Option<Edited> {
+    let from = renamed(rules, edit);
+    let tags: Vec<String> = YamlLoader::load_from_str(&header[start]["tags:".len()..])
+        .ok()?
+        .first()?
+        .as_vec()?
+        .iter()
+        .filter_map(|t| t.as_str().map(String::from))
+        .collect();
+
+    let mut new_tags: Vec<String> = vec![];
+    for t in &tags {
+        match edit {
+            TagEdit::Rename(_, to) => {
+                let nt = if normalize(rules, t).is_some_and(|t| from.contains(&t)) {
+                    to.to_string()
+                } else {
+                    t.clone()
+                };
+                if !same(rules, &nt, to) || !new_tags.iter().any(|t| same(rules, t, to)) {
+                    new_tags.push(nt);
+                }
+            }
+            TagEdit::Remove(r) if same(rules, t, r) => {}
+            _ => new_tags.push(t.clone()),
+        }
+    }
+    if let TagEdit::Add(a) = edit {
+        if !new_tags.iter().any(|t| same(rules, t, a)) {
+            new_tags.push(a.to_string());
+        }
+    }
+    if new_tags == tags {
+        return None;
+    }
+
+    let quoted: Vec<String> = new_tags.iter().map(|t| quote(t)).collect();
+    let mut out: Vec<String> = header[..start].iter().map(|l| l.to_string()).collect();
+    out.push(format!(
+        "tags: [{}]{}",
+        quoted.join(", "),
+        line_end(header[start])
+    ));
+    Some((out, start + 1))
+}
+
+fn replace_item(line: &str, tag: &str) -> String {
+    let value = line.trim_start_matches(|c: char| c.is_whitespace() || c == '-');
+    let prefix = &line[..line.len() - value.len()];
+    format!("{}{}{}", prefix, quote(tag), line_end(line))
+}
+
+fn is_list_item(line: &str) -> bool {
+    let l = line.trim_start();
+    l == "-"
+        || l.starts_with("- ")
+        || l.starts_with("-\t")
+        || l.starts_with("#")
+        || l.trim().is_empty()
+}
+
+fn item_value(line: &str) -> &str {
+    line.trim_start().trim_start_matches('-').trim()
+}
+
+fn scalar(s: &str) -> Option<String> {
+    YamlLoader::load_from_str(s)
+        .ok()?
+        .first()?
+        .as_str()
+        .map(String::from)
+}
+
+fn line_end(line: &str) -> &str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else if line.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+// plain when yaml reads it back as is
+fn quote(tag: &str) -> String {
+    if scalar(tag).as_deref() == Some(tag) && !tag.contains(',') && !tag.contains(']') {
+        return tag.into();
+    }
+    format!("\"{}\"", tag.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn new_article_basic() -> std::io::Result<()> {
+        assert_eq!(
+            "---\ntitle: \"my: title\"\ntags:\n  - rust\n  - elm\n---\n\nbody\n",
+            new_article("my: title", &["rust".into(), "elm".into()], "body\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn file_name_basic() -> std::io::Result<()> {
+        assert_eq!("my-cool-title.md", file_name("My cool  title !"));
+        assert_eq!("article.md", file_name("?!"));
+        Ok(())
+    }
+
+    #[test]
+    fn edit_tags_rename() -> std::io::Result<()> {
+        let from = vec!["ml".to_string(), "ML".to_string()];
+        let rename = |c: &str| {
+            edit_tags(
+                c.as_bytes(),
+                &TagEdit::Rename(&from, "machine-learning"),
+                &TagRules::default(),
+            )
+            .map(|c| String::from_utf8(c).unwrap())
+        };
+
+        // only the tag lines change
+        assert_eq!(
+            Some("---\r\ntitle: t # ml\r\ntags:\r\n  - rust\r\n  - machine-learning\r\nother: ml\r\n---\r\n- ml\r\n".into()),
+            rename("---\r\ntitle: t # ml\r\ntags:\r\n  - rust\r\n  - \"ml\"\r\nother: ml\r\n---\r\n- ml\r\n")
+        );
+        // merged
+        assert_eq!(
+            Some("---\ntags:\n- machine-learning\n- rust\n---\n".into()),
+            rename("---\ntags:\n- machine-learning\n- rust\n- ML\n---\n")
+        );
+        assert_eq!(
+            Some("---\ntags: [machine-learning, rust]\n---\nbody".into()),
+            rename("---\ntags: [ml, rust, ML]\n---\nbody")
+        );
+        assert_eq!(
+            Some(b"---\ntags:\n- \"a: b\"\n---\n".to_vec()),
+            edit_tags(
+                b"---\ntags:\n- ml\n---\n",
+                &TagEdit::Rename(&from, "a: b"),
+                &TagRules::default()
+            )
+        );
+        // untouched
+        assert_eq!(None, rename("---\ntags:\n- rust\n---\n- ml\n"));
+        assert_eq!(None, rename("no header\n"));
+
+        // the body is copied as is
+        let mut content = b"---\ntags:\n- ml\n---\n".to_vec();
+        content.extend(vec![0xff, 0xfe, b'\n']);
+        let mut expected = b"---\ntags:\n- machine-learning\n---\n".to_vec();
+        expected.extend(vec![0xff, 0xfe, b'\n']);
+        assert_eq!(
+            Some(expected),
+            edit_tags(
+                &content,
+                &TagEdit::Rename(&from, "machine-learning"),
+                &TagRules::default()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn edit_tags_add_remove() -> std::io::Result<()> {
+        let edit = |c: &str, e: TagEdit| {
+            edit_tags(c.as_bytes(), &e, &TagRules::default()).map(|c| String::from_utf8(c).unwrap())
+        };
+
+        let block = "---\ntitle: t\ntags:\n    - rust\n    - elm\n\nother: o\n- - -\n- rust\n";
+        assert_eq!(
+            Some("---\ntitle: t\ntags:\n    - rust\n    - elm\n    - go\n\nother: o\n- - -\n- rust\n".into()),
+            edit(block, TagEdit::Add("go"))
+        );
+        assert_eq!(
+            Some("---\ntitle: t\ntags:\n    - elm\n\nother: o\n- - -\n- rust\n".into()),
+            edit(block, TagEdit::Remove("rust"))
+        );
+        assert_eq!(None, edit(block, TagEdit::Add("elm")));
+        assert_eq!(None, edit(block, TagEdit::Remove("go")));
+
+        assert_eq!(
+            Some("---\ntags: [rust, go]\n---\n".into()),
+            edit("---\ntags: [rust]\n---\n", TagEdit::Add("go"))
+        );
+        assert_eq!(
+            Some("---\ntags: []\n---\n".into()),
+            edit("---\ntags: [rust]\n---\n", TagEdit::Remove("rust"))
+        );
+        // no tags yet
+        assert_eq!(
+            Some("---\ntitle: t\ntags:\n- go\n---\nbody".into()),
+            edit("---\ntitle: t\n---\nbody", TagEdit::Add("go"))
+        );
+        assert_eq!(
+            Some("---\ntags:\n- go\n---\n".into()),
+            edit("---\ntags:\n---\n", TagEdit::Add("go"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn edit_tags_normalized() -> std::io::Result<()> {
+        let rules = TagRules {
+            lowercase: true,
+            separator: "-".into(),
+            ..TagRules::default()
+        };
+        let edit = |c: &str, e: TagEdit| {
+            edit_tags(c.as_bytes(), &e, &rules).map(|c| String::from_utf8(c).unwrap())
+        };
+
+        let from = vec!["machine learning".to_string()];
+        assert_eq!(
+            Some("---\ntags:\n- machine-learning\n- rust\n---\n".into()),
+            edit(
+                "---\ntags:\n- Machine Learning\n- rust\n- machine-learning\n---\n",
+                TagEdit::Rename(&from, "machine-learning")
+            )
+        );
+        assert_eq!(
+            Some("---\ntags: [machine-learning]\n---\n".into()),
+            edit(
+                "---\ntags: [ML, Machine Learning]\n---\n",
+                TagEdit::Rename(&["ml".to_string()], "machine-learning")
+            )
+        );
+        assert_eq!(
+            Some("---\ntags: [elm]\n---\n".into()),
+            edit("---\ntags: [Rust, elm]\n---\n", TagEdit::Remove("rust"))
+        );
+        assert_eq!(
+            None,
+            edit("---\ntags:\n- Rust\n---\n", TagEdit::Add("RUST"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn asset_name_basic() -> std::io::Result<()> {
+        let png = b"\x89PNG\r\n\x1a\nrest".to_vec();
+        let name = asset_name(&png).unwrap();
+        assert!(name.ends_with(".png"));
+        assert_eq!(32 + 4, name.len());
+        assert_eq!(Some(name), asset_name(&png));
+        assert_ne!(asset_name(&png), asset_name(b"\x89PNG\r\n\x1a\nother"));
+
+        assert_eq!(Some("image/webp"), image_type(b"RIFF\0\0\0\0WEBPVP8"));
+        assert_eq!(None, image_type(b"RIFF\0\0\0\0WAVE"));
+        assert_eq!(None, asset_name(b"<svg></svg>"));
+        Ok(())
+    }
+
+    #[test]
+    fn write_atomic_basic() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let p = dir.path().join("a.md");
+        fs::write(&p, "old")?;
+        let before = etag(&p)?;
+
+        write_atomic(&p, b"new content")?;
+        assert_eq!("new content", fs::read_to_string(&p)?);
+        assert_ne!(before, etag(&p)?);
+        assert_eq!(1, fs::read_dir(dir.path())?.count()); // no temp file left
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+// who may read and write through the api : bearer tokens and basic auth users, each with a
+// role ; without an [auth] section the api is open for reading, and for writing with the
+// write token only
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use dashmap::DashMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Read,
+    Write, // reads too
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Token {
+    pub token: String, // as is or hashed ("sha256:<hex>"), see `knowledge hash-token`
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct User {
+    pub name: String,
+    pub password: String, // hashed, see `knowledge hash-password`
+    pub role: Role,
+}
+
+// the [auth] section of the config
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub tokens: Vec<Token>,
+    pub users: Vec<User>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Denied {
+    Unauthenticated, // 401, the client may retry with credentials
+    Forbidden,       // 403, the role isn't enough (or nobody may write)
+}
+
+// the sha256 of a token : the tokens themselves aren't kept
+#[derive(Clone, Debug)]
+struct TokenHash {
+    hash: Vec<u8>,
+    role: Role,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Auth {
+    tokens: Vec<TokenHash>,
+    users: Vec<User>,
+    configured: bool, // by an [auth] section, else the reads are open
+    // the hashes are slow to check on every request : the verified headers are kept
+    verified: Arc<DashMap<Vec<u8>, Role>>,
+}
+
+impl Auth {
+    // write_token : the read-write token of the first versions
+    pub fn new(cfg: &AuthConfig, write_token: Option<String>) -> Self {
+        let mut tokens: Vec<TokenHash> = cfg
+            .tokens
+            .iter()
+            .map(|t| TokenHash {
+                hash: token_hash(&t.token),
+                role: t.role,
+            })
+            .collect();
+        if let Some(token) = write_token {
+            tokens.push(TokenHash {
+                hash: digest(&token),
+                role: Role::Write,
+            });
+        }
+        Auth {
+            tokens,
+            users: cfg.users.clone(),
+            configured: !cfg.tokens.is_empty() || !cfg.users.is_empty(),
+            verified: Arc::new(DashMap::new()),
+        }
+    }
+
+    // the legacy write token alone keeps the reads open, not a write token of [auth]
+    pub fn reads_are_open(&self) -> bool {
+        !self.configured
+    }
+
+    // authorization : the header, if any
+    pub fn check(&self, authorization: Option<&str>, needed: Role) -> Result<(), Denied> {
+        let nobody_may = !self.tokens.iter().any(|t| t.role >= needed)
+            && !self.users.iter().any(|u| u.role >= needed);
+        if needed == Role::Read && self.reads_are_open() {
+            return Ok(());
+        }
+        if nobody_may {
+            return Err(Denied::Forbidden);
+        }
+
+        match authorization.and_then(|a| self.role(a)) {
+            Some(role) if role >= needed => Ok(()),
+            Some(_) => Err(Denied::Forbidden),
+            None => Err(Denied::Unauthenticated),
+        }
+    }
+
+    // the challenge sent with a 401
+    pub fn challenge(&self) -> &'static str {
+        if self.users.is_empty() {
+            "Bearer"
+        } else {
+            "Basic realm=\"knowledge\", charset=\"UTF-8\""
+        }
+    }
+
+    fn role(&self, authorization: &str) -> Option<Role> {
+        let key = digest(authorization);
+        if let Some(role) = self.verified.get(&key) {
+            return Some(*role);
+        }
+
+        let role = if let Some(token) = authorization.strip_prefix("Bearer ") {
+            self.tokens
+                .iter()
+                .find(|t| t.hash == digest(token.trim()))
+                .map(|t| t.role)
+        } else if let Some(basic) = authorization.strip_prefix("Basic ") {
+            let decoded = base64::decode(basic.trim()).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (name, password) = decoded.split_once(':')?;
+            self.users
+                .iter()
+                .find(|u| u.name == name && verify_password(password, &u.password))
+                .map(|u| u.role)
+        } else {
+            None
+        }?;
+        self.verified.insert(key, role);
+        Some(role)
+    }
+}
+
+// same length whatever the input, compared without leaking where they differ
+fn digest(s: &str) -> Vec<u8> {
+    Sha256::digest(s.as_bytes()).to_vec()
+}
+
+// "sha256:<hex>" for the config, the token is compared by its hash
+pub fn hash_token(token: &str) -> String {
+    let hex: String = digest(token).iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+// the hash of a configured token, hashed already or not
+fn token_hash(token: &str) -> Vec<u8> {
+    let hashed = token
+        .strip_prefix("sha256:")
+        .filter(|h| h.len() == 64)
+        .and_then(|h| {
+            (0..h.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(h.get(i..i + 2)?, 16).ok())
+                .collect::<Option<Vec<u8>>>()
+        });
+    hashed.unwrap_or_else(|| digest(token))
+}
+
+// a phc string ($argon2id$...)
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt =
+        SaltString::encode_b64(&rand::thread_rng().gen::<[u8; 16]>()).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|h| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &h)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", base64::encode(credentials))
+    }
+
+    #[test]
+    fn roles() -> std::io::Result<()> {
+        // as before : open reads, writes with the token only
+        let legacy = Auth::new(&AuthConfig::default(), Some("w".into()));
+        assert_eq!(Ok(()), legacy.check(None, Role::Read));
+        assert_eq!(Ok(()), legacy.check(Some("Bearer w"), Role::Write));
+        assert_eq!(
+            Err(Denied::Unauthenticated),
+            legacy.check(None, Role::Write)
+        );
+        let closed = Auth::new(&AuthConfig::default(), None);
+        assert_eq!(Ok(()), closed.check(None, Role::Read));
+        assert_eq!(Err(Denied::Forbidden), closed.check(None, Role::Write));
+
+        // only a write token in [auth] : it's needed to read too
+        let writers = AuthConfig {
+            tokens: vec![Token {
+                token: "w".into(),
+                role: Role::Write,
+            }],
+            users: vec![],
+        };
+        let writers = Auth::new(&writers, None);
+        assert!(!writers.reads_are_open());
+        assert_eq!(
+            Err(Denied::Unauthenticated),
+            writers.check(None, Role::Read)
+        );
+        assert_eq!(Ok(()), writers.check(Some("Bearer w"), Role::Read));
+
+        let cfg = AuthConfig {
+            tokens: vec![Token {
+                token: "r".into(),
+                role: Role::Read,
+            }],
+            users: vec![User {
+                name: "alice".into(),
+                password: hash_password("s3cret:!").unwrap(),
+                role: Role::Write,
+            }],
+        };
+        let auth = Auth::new(&cfg, None);
+        assert!(auth.challenge().starts_with("Basic"));
+        assert_eq!(Err(Denied::Unauthenticated), auth.check(None, Role::Read));
+        assert_eq!(Ok(()), auth.check(Some("Bearer r"), Role::Read));
+        assert_eq!(
+            Err(Denied::Forbidden),
+            auth.check(Some("Bearer r"), Role::Write)
+        );
+        assert_eq!(
+            Err(Denied::Unauthenticated),
+            auth.check(Some("Bearer w"), Role::Read)
+        );
+
+        let alice = basic("alice:s3cret:!");
+        assert_eq!(Ok(()), auth.check(Some(&alice), Role::Write));
+        // once verified, from the cache
+        assert_eq!(Ok(()), auth.check(Some(&alice), Role::Read));
+        assert_eq!(
+            Err(Denied::Unauthenticated),
+            auth.check(Some(&basic("alice:wrong")), Role::Read)
+        );
+        assert_eq!(
+            Err(Denied::Unauthenticated),
+            auth.check(Some(&basic("bob:s3cret:!")), Role::Read)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hashed_tokens() -> std::io::Result<()> {
+        let hashed = hash_token("t0k3n");
+        assert!(hashed.starts_with("sha256:"));
+        assert_eq!(token_hash("t0k3n"), token_hash(&hashed));
+
+        let cfg = AuthConfig {
+            tokens: vec![Token {
+                token: hashed.clone(),
+                role: Role::Read,
+            }],
+            users: vec![],
+        };
+        let auth = Auth::new(&cfg, None);
+        assert_eq!(Ok(()), auth.check(Some("Bearer t0k3n"), Role::Read));
+        // the hash isn't a token
+        assert_eq!(
+            Err(Denied::Unauthenticated),
+            auth.check(Some(&format!("Bearer {}", hashed)), Role::Read)
+        );
+        assert!(!format!("{:?}", auth).contains("t0k3n"));
+        Ok(())
+    }
+
+    #[test]
+    fn hashed_passwords() -> std::io::Result<()> {
+        let hash = hash_password("pw").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password("pw").unwrap()); // salted
+        assert!(verify_password("pw", &hash));
+        assert!(!verify_password("pW", &hash));
+        assert!(!verify_password("pw", "pw")); // never in clear
+        Ok(())
+    }
+}
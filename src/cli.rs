@@ -0,0 +1,237 @@
+// the headless subcommands : a scan of the roots, the results on stdout
+use crate::domain::{ArtRef, Heading, Link, Op};
+use crate::storage::Store;
+use crate::uc::{self, Query};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub fn tags(s: &Store, json: bool) -> io::Result<String> {
+    let tags = s.tag_counts();
+    if json {
+        return to_json(&tags);
+    }
+    Ok(table(
+        &["TAG", "ARTICLES"],
+        tags.into_iter()
+            .map(|t| vec![t.tag, t.count.to_string()])
+            .collect(),
+    ))
+}
+
+// the articles bearing all the tags (every article without tags)
+pub fn list(tags: &[String], root: &Option<String>, s: &Store, json: bool) -> io::Result<String> {
+    let query = tags
+        .iter()
+        .cloned()
+        .map(Query::Sing)
+        .reduce(|a, b| Query::Comb(Op::And, Box::new(a), Box::new(b)));
+    let arts = match query {
+        Some(q) => uc::search_by_tag(&q, s),
+        None => s.get_all_articles().into_iter().map(|m| m.art).collect(),
+    };
+    articles(uc::filter_by_root(arts, root), s, json)
+}
+
+pub fn search(query: &str, s: &Store, json: bool) -> io::Result<String> {
+    let q = query
+        .parse::<Query>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    articles(uc::search_by_tag(&q, s), s, json)
+}
+
+// the local path is shown, unlike in the api
+#[derive(Serialize)]
+struct Article {
+    path: PathBuf,
+    #[serde(flatten)]
+    art: ArtRef,
+    tags: Vec<String>,
+    toc: Vec<Heading>,
+    links: Vec<Link>,
+    content: String,
+}
+
+// p : a path or an article id
+pub fn show(p: &str, s: &Store, json: bool) -> io::Result<String> {
+    // an id wins over a file of the current folder
+    let p = match s.get_by_id(p) {
+        Some(m) => m.art.path,
+        None => match fs::canonicalize(p) {
+            Ok(p) => p,
+            Err(_) => uc::resolve(p, s)?.into(),
+        },
+    };
+    let m = s
+        .get_by_path(&p)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not an article"))?;
+    let content = fs::read_to_string(&p)?;
+    if !json {
+        return Ok(content);
+    }
+
+    let path = p.to_string_lossy();
+    to_json(&Article {
+        path: m.art.path.clone(),
+        toc: uc::article_toc(&path, s)?,
+        links: uc::article_links(&path, s)?,
+        art: m.art,
+        tags: m.tags,
+        content,
+    })
+}
+
+fn articles(mut arts: Vec<ArtRef>, s: &Store, json: bool) -> io::Result<String> {
+    arts.sort_by(|a, b| a.id.cmp(&b.id));
+    let arts: Vec<(ArtRef, Vec<String>)> = arts
+        .into_iter()
+        .map(|a| {
+            let tags = s.get_by_path(&a.path).map(|m| m.tags).unwrap_or_default();
+            (a, tags)
+        })
+        .collect();
+
+    if json {
+        #[derive(Serialize)]
+        struct Entry<'a> {
+            path: &'a Path,
+            #[serde(flatten)]
+            art: &'a ArtRef,
+            tags: &'a [String],
+        }
+        let entries: Vec<Entry> = arts
+            .iter()
+            .map(|(art, tags)| Entry {
+                path: &art.path,
+                art,
+                tags,
+            })
+            .collect();
+        return to_json(&entries);
+    }
+    Ok(table(
+        &["ID", "TITLE", "TAGS", "PATH"],
+        arts.into_iter()
+            .map(|(a, tags)| {
+                vec![
+                    a.id,
+                    a.title,
+                    tags.join(","),
+                    a.path.to_string_lossy().into(),
+                ]
+            })
+            .collect(),
+    ))
+}
+
+fn to_json<T: Serialize>(v: &T) -> io::Result<String> {
+    Ok(serde_json::to_string_pretty(v)? + "\n")
+}
+
+// columns padded to their widest cell, the last one isn't
+fn table(header: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+
+    let header = header.iter().map(|h| h.to_string()).collect();
+    let mut out = String::new();
+    for row in std::iter::once(header).chain(rows) {
+        let last = row.len().saturating_sub(1);
+        for (i, cell) in row.iter().enumerate() {
+            if i == last {
+                out.push_str(cell);
+            } else {
+                let pad = widths[i] - cell.chars().count();
+                out.push_str(&format!("{}{}  ", cell, " ".repeat(pad)));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Root, Rules, SymlinkPolicy};
+    use tempfile::tempdir;
+
+    #[test]
+    fn table_basic() -> std::io::Result<()> {
+        let rows = vec![
+            vec!["rust".into(), "12".into()],
+            vec!["ownership".into(), "3".into()],
+        ];
+        assert_eq!(
+            "TAG        ARTICLES\nrust       12\nownership  3\n",
+            table(&["TAG", "ARTICLES"], rows)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn queries() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join("a.md"),
+            "---\ntitle: A\ntags: [rust, web]\n---\n# Intro\n",
+        )?;
+        fs::write(
+            dir.path().join("b.md"),
+            "---\ntitle: B\ntags: [rust]\n---\n",
+        )?;
+        let s = uc::scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+
+        assert_eq!("TAG   ARTICLES\nrust  2\nweb   1\n", tags(&s, false)?);
+        let ids = |out: String| -> Vec<String> {
+            out.lines()
+                .skip(1)
+                .map(|l| l.split_whitespace().next().unwrap_or("").to_string())
+                .collect()
+        };
+        assert_eq!(vec!["a", "b"], ids(list(&[], &None, &s, false)?));
+        assert_eq!(
+            vec!["a"],
+            ids(list(&["rust".into(), "web".into()], &None, &s, false)?)
+        );
+        assert_eq!(vec!["a"], ids(search("web or (rust and nope)", &s, false)?));
+        assert!(search("rust and", &s, false).is_err());
+
+        let json = list(&["web".into()], &None, &s, true)?;
+        assert!(json.contains("\"id\": \"a\"") && json.contains("\"tags\": ["));
+        assert!(json.contains("\"path\": "));
+
+        let a = dir.path().join("a.md");
+        assert!(show("a", &s, false)?.starts_with("---\ntitle: A"));
+        let json = show(&a.to_string_lossy(), &s, true)?;
+        assert!(json.contains("\"slug\": \"intro\""));
+        assert!(show("nope", &s, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn show_by_id_first() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        // "src" is a folder of the current one too
+        fs::write(
+            dir.path().join("a.md"),
+            "---\ntitle: A\nid: src\n---\nhello\n",
+        )?;
+        let s = uc::scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+        assert!(show("src", &s, false)?.ends_with("hello\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,697 @@
+// the settings, by increasing priority : the defaults, $XDG_CONFIG_HOME/knowledge/config.toml,
+// knowledge.toml in the (first) root folder, the KNOWLEDGE_* env vars, then the flags
+use crate::auth::{Auth, AuthConfig};
+use crate::disk_backend::DiskBackend;
+use crate::domain::{check_root_names, IgnoreRules, Root, Rules, SymlinkPolicy, TagRules};
+use crate::storage::Store;
+use crate::tls;
+use clap::ArgMatches;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+pub static FILE_NAME: &str = "knowledge.toml";
+static ENV_PREFIX: &str = "KNOWLEDGE_";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub folders: Vec<String>, // "path" or "name=path", the current folder when empty
+    pub port: u16,            // 0 : a random one
+    pub bind: String,         // "host", "host:port" or "unix:/path/to.sock"
+    pub open_browser: bool,
+    pub dev: bool,
+    pub symlinks: SymlinkPolicy,
+    pub debounce: u64,             // ms
+    pub write_token: String,       // the write api is disabled when empty
+    pub attachments: String,       // relative to the articles
+    pub cors_origins: Vec<String>, // allowed besides the app itself
+    pub log_level: String,
+    pub index_dir: String, // the index kept on disk between runs, in memory when empty
+    pub tls_cert: String,  // pem files, https when given
+    pub tls_key: String,
+    pub self_signed: bool,   // https with a certificate made on the first run
+    pub http_port: u16,      // with https : plain http redirected to it, 0 : none
+    pub ignore: Vec<String>, // gitignore like patterns
+    pub tags: TagRules,
+    pub auth: AuthConfig,
+    pub vaults: BTreeMap<String, VaultConfig>, // served under /<name>, instead of the folders
+}
+
+// a knowledge base of its own, [vaults.<name>] ; the other settings are shared
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VaultConfig {
+    pub folders: Vec<String>,
+    pub index_dir: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            folders: vec![],
+            port: 0,
+            bind: "localhost".into(),
+            open_browser: true,
+            dev: false,
+            symlinks: SymlinkPolicy::Follow,
+            debounce: 200,
+            write_token: String::new(),
+            attachments: "attachments".into(),
+            cors_origins: vec![],
+            log_level: "error".into(),
+            index_dir: String::new(),
+            tls_cert: String::new(),
+            tls_key: String::new(),
+            self_signed: false,
+            http_port: 0,
+            ignore: vec![],
+            tags: TagRules::default(),
+            auth: AuthConfig::default(),
+            vaults: BTreeMap::new(),
+        }
+    }
+}
+
+// where the server listens
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listen {
+    Tcp(String, u16), // host, port
+    Unix(PathBuf),
+}
+
+impl Listen {
+    // what a browser on this machine opens, None behind a socket
+    pub fn url(&self, https: bool) -> Option<String> {
+        match self {
+            Listen::Tcp(host, port) => {
+                let host = match host.as_str() {
+                    "0.0.0.0" | "::" | "[::]" => "localhost",
+                    h => h,
+                };
+                let scheme = if https { "https" } else { "http" };
+                Some(format!("{}://{}:{}", scheme, host, port))
+            }
+            Listen::Unix(_) => None,
+        }
+    }
+}
+
+impl Config {
+    // a port given in bind wins, then 8080 in dev mode, then the port setting
+    pub fn listen(&self) -> io::Result<Listen> {
+        if let Some(p) = self.bind.strip_prefix("unix:") {
+            return Ok(Listen::Unix(p.into()));
+        }
+
+        let (host, port) = match self.bind.rsplit_once(':') {
+            // a bare ipv6 address has several colons
+            Some((h, p)) if !h.contains(':') || h.ends_with(']') => (h, Some(p)),
+            _ => (self.bind.as_str(), None),
+        };
+        let port = match port {
+            Some(p) => p
+                .parse()
+                .map_err(|_| invalid(format!("invalid port in bind: {}", self.bind)))?,
+            None if self.dev => 8080,
+            None if self.port == 0 => rand::thread_rng().gen_range(3000, 10000),
+            None => self.port,
+        };
+        Ok(Listen::Tcp(host.into(), port))
+    }
+
+    pub fn roots(&self) -> io::Result<Vec<Root>> {
+        if self.folders.is_empty() {
+            return Ok(vec![Root::new(env::current_dir()?)]);
+        }
+        let roots = self
+            .folders
+            .iter()
+            .map(|f| f.parse().map_err(invalid))
+            .collect::<io::Result<Vec<Root>>>()?;
+        check_root_names(&roots).map_err(invalid)?;
+        Ok(roots)
+    }
+
+    // the settings of each vault and its url prefix : the configured ones, or a single one
+    // made of the folders, at the root
+    pub fn vaults(&self) -> io::Result<Vec<(String, Config)>> {
+        if self.vaults.is_empty() {
+            return Ok(vec![(String::new(), self.clone())]);
+        }
+
+        let mut index_dirs = vec![];
+        let mut vaults = vec![];
+        for (name, v) in &self.vaults {
+            let url_safe = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if name.is_empty() || !name.chars().all(url_safe) {
+                return Err(invalid(format!("invalid vault name: {}", name)));
+            }
+            if v.folders.is_empty() {
+                return Err(invalid(format!("the vault {} has no folders", name)));
+            }
+            if !v.index_dir.is_empty() && index_dirs.contains(&&v.index_dir) {
+                return Err(invalid(format!("the vaults share {}", v.index_dir)));
+            }
+            index_dirs.push(&v.index_dir);
+            let c = Config {
+                folders: v.folders.clone(),
+                index_dir: v.index_dir.clone(),
+                vaults: BTreeMap::new(),
+                ..self.clone()
+            };
+            vaults.push((format!("/{}", name), c));
+        }
+        Ok(vaults)
+    }
+
+    pub fn rules(&self) -> io::Result<Rules> {
+        Ok(Rules {
+            ignore: IgnoreRules::new(&self.ignore).map_err(invalid)?,
+            tags: self.tags.clone(),
+        })
+    }
+
+    pub fn https(&self) -> bool {
+        !self.tls_cert.is_empty() || self.self_signed
+    }
+
+    // the certificate files, or the self-signed ones ; None without https
+    pub fn tls(&self) -> io::Result<Option<tls::Https>> {
+        let given = (!self.tls_cert.is_empty(), !self.tls_key.is_empty());
+        let (cert, key) = match (given, self.self_signed) {
+            ((true, true), _) => (self.tls_cert.clone().into(), self.tls_key.clone().into()),
+            ((false, false), true) => {
+                let d = dir().ok_or_else(|| invalid("no config folder for the certificate"))?;
+                tls::self_signed(&d.join("tls"), &self.host_names())?
+            }
+            ((false, false), false) if self.http_port != 0 => {
+                return Err(invalid("http_port redirects to https, which isn't enabled"))
+            }
+            ((false, false), false) => return Ok(None),
+            _ => return Err(invalid("tls_cert and tls_key go together")),
+        };
+        Ok(Some(tls::Https {
+            config: tls::server_config(&cert, &key)?,
+            http_port: Some(self.http_port).filter(|p| *p != 0),
+        }))
+    }
+
+    // the names of this machine, for a self-signed certificate
+    fn host_names(&self) -> Vec<String> {
+        let mut names = vec!["localhost".to_string(), "127.0.0.1".into(), "::1".into()];
+        if let Ok(h) = hostname::get() {
+            names.push(h.to_string_lossy().into());
+        }
+        if let Ok(Listen::Tcp(host, _)) = self.listen() {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            if !["0.0.0.0", "::"].contains(&host) {
+                names.push(host.into());
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // an empty store, with its backend and rules
+    pub fn store(&self) -> io::Result<Store> {
+        let store = if self.index_dir.is_empty() {
+            Store::new()
+        } else {
+            Store::with_backend(DiskBackend::open(Path::new(&self.index_dir))?)
+        };
+        store.set_rules(self.rules()?);
+        Ok(store)
+    }
+
+    pub fn log_level(&self) -> io::Result<log::LevelFilter> {
+        self.log_level
+            .parse()
+            .map_err(|_| invalid(format!("unknown log level: {}", self.log_level)))
+    }
+
+    pub fn auth(&self) -> Auth {
+        let write_token = Some(self.write_token.clone()).filter(|t| !t.is_empty());
+        Auth::new(&self.auth, write_token)
+    }
+
+    // toml, the tokens hidden (the passwords are hashed)
+    pub fn show(&self) -> io::Result<String> {
+        let hidden = "********".to_string();
+        let mut shown = self.clone();
+        if !shown.write_token.is_empty() {
+            shown.write_token = hidden.clone();
+        }
+        for t in shown.auth.tokens.iter_mut() {
+            t.token = hidden.clone();
+        }
+        toml::to_string_pretty(&shown).map_err(invalid)
+    }
+}
+
+// the config and where it comes from
+pub fn load(mm: &ArgMatches) -> io::Result<(Config, Vec<String>)> {
+    let user_file = dir().map(|d| d.join("config.toml"));
+    load_from(flags(mm)?, env::vars(), user_file.as_deref())
+}
+
+// $XDG_CONFIG_HOME/knowledge, or ~/.config/knowledge
+fn dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+        .map(|d| d.join("knowledge"))
+}
+
+fn load_from(
+    flags: Table,
+    vars: impl Iterator<Item = (String, String)>,
+    user_file: Option<&Path>,
+) -> io::Result<(Config, Vec<String>)> {
+    let defaults = table(&Config::default())?;
+    let vars = env_layer(vars, &defaults)?;
+    let mut config = defaults;
+    let mut sources = vec!["defaults".to_string()];
+
+    if let Some(user) = read_file(user_file)? {
+        merge(&mut config, user);
+        sources.push(user_file.unwrap_or(Path::new("")).display().to_string());
+    }
+
+    // the root folder is given by the layers above its file
+    let folders = [&flags, &vars, &config]
+        .iter()
+        .find_map(|t| {
+            t.get("folders")
+                .and_then(Value::as_array)
+                .filter(|f| !f.is_empty())
+        })
+        .and_then(|f| f[0].as_str())
+        .map(|f| f.parse::<Root>().map_err(invalid))
+        .transpose()?;
+    let root = match folders {
+        Some(r) => r.path,
+        None => env::current_dir()?,
+    };
+    let root_file = root.join(FILE_NAME);
+    if let Some(layer) = read_file(Some(&root_file))? {
+        merge(&mut config, layer);
+        sources.push(root_file.display().to_string());
+    }
+
+    if !vars.is_empty() {
+        merge(&mut config, vars);
+        sources.push("env".into());
+    }
+    if !flags.is_empty() {
+        merge(&mut config, flags);
+        sources.push("flags".into());
+    }
+
+    let config = Value::Table(config).try_into().map_err(invalid)?;
+    Ok((config, sources))
+}
+
+fn table(c: &Config) -> io::Result<Table> {
+    match Value::try_from(c).map_err(invalid)? {
+        Value::Table(t) => Ok(t),
+        _ => Err(invalid("not a table")),
+    }
+}
+
+fn read_file(p: Option<&Path>) -> io::Result<Option<Table>> {
+    let p = match p.filter(|p| p.is_file()) {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let mut t: Table = toml::from_str(&fs::read_to_string(p)?)
+        .map_err(|e| invalid(format!("{}: {}", p.display(), e)))?;
+
+    let dir = p.parent().unwrap_or(Path::new(""));
+    rebase(&mut t, dir)?;
+    if let Some(Value::Table(vaults)) = t.get_mut("vaults") {
+        for (_, v) in vaults.iter_mut() {
+            if let Value::Table(v) = v {
+                rebase(v, dir)?;
+            }
+        }
+    }
+    Ok(Some(t))
+}
+
+// the folders, the index and the certificate files of a file are relative to it
+fn rebase(t: &mut Table, dir: &Path) -> io::Result<()> {
+    if let Some(Value::Array(folders)) = t.get_mut("folders") {
+        for f in folders.iter_mut() {
+            let root: Root = f
+                .as_str()
+                .ok_or_else(|| invalid("folders must be strings"))?
+                .parse()
+                .map_err(invalid)?;
+            let rebased = format!("{}={}", root.name, dir.join(root.path).display());
+            *f = Value::String(rebased);
+        }
+    }
+    for k in ["index_dir", "tls_cert", "tls_key"] {
+        if let Some(Value::String(f)) = t.get_mut(k) {
+            if !f.is_empty() {
+                *f = dir.join(&f).display().to_string();
+            }
+        }
+    }
+    Ok(())
+}
+
+// KNOWLEDGE_LOG_LEVEL=info, KNOWLEDGE_TAGS_LOWERCASE=true, KNOWLEDGE_IGNORE=drafts,*.tmp ;
+// the unknown ones are left alone
+fn env_layer(vars: impl Iterator<Item = (String, String)>, defaults: &Table) -> io::Result<Table> {
+    let mut keys = vec![];
+    flat_keys(defaults, &mut vec![], &mut keys);
+
+    let mut layer = Table::new();
+    for (name, value) in vars {
+        let name = match name.strip_prefix(ENV_PREFIX) {
+            Some(n) => n.to_lowercase(),
+            None => continue,
+        };
+        if let Some((path, default)) = keys.iter().find(|(path, _)| path.join("_") == name) {
+            let value = typed(&value, default).map_err(|e| invalid(format!("{}: {}", name, e)))?;
+            insert(&mut layer, path, value);
+        }
+    }
+    Ok(layer)
+}
+
+fn flat_keys(t: &Table, path: &mut Vec<String>, keys: &mut Vec<(Vec<String>, Value)>) {
+    for (k, v) in t {
+        path.push(k.clone());
+        keys.push((path.clone(), v.clone()));
+        if let Value::Table(inner) = v {
+            flat_keys(inner, path, keys);
+        }
+        path.pop();
+    }
+}
+
+fn insert(t: &mut Table, path: &[String], value: Value) {
+    match path {
+        [] => {}
+        [k] => {
+            t.insert(k.clone(), value);
+        }
+        [k, rest @ ..] => {
+            let inner = t
+                .entry(k.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if let Value::Table(inner) = inner {
+                insert(inner, rest, value);
+            }
+        }
+    }
+}
+
+// a string of the env or of the flags, typed like the default value
+fn typed(s: &str, default: &Value) -> Result<Value, String> {
+    let parsed = || {
+        toml::from_str::<Table>(&format!("v = {}", s))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+    };
+    Ok(match default {
+        Value::String(_) => Value::String(s.into()),
+        Value::Integer(_) => Value::Integer(s.trim().parse().map_err(|_| "not a number")?),
+        Value::Boolean(_) => Value::Boolean(match s.trim() {
+            "true" | "1" | "yes" => true,
+            "false" | "0" | "no" => false,
+            _ => return Err("not a boolean".into()),
+        }),
+        Value::Array(_) => match parsed() {
+            Some(a @ Value::Array(_)) => a,
+            _ => Value::Array(
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(|v| Value::String(v.into()))
+                    .collect(),
+            ),
+        },
+        Value::Table(_) => match parsed() {
+            Some(t @ Value::Table(_)) => t,
+            _ => return Err("not an inline table".into()),
+        },
+        _ => parsed().ok_or("invalid value")?,
+    })
+}
+
+// the tables are merged, the other values replaced
+fn merge(base: &mut Table, layer: Table) {
+    for (k, v) in layer {
+        match (base.get_mut(&k), v) {
+            (Some(Value::Table(b)), Value::Table(l)) => merge(b, l),
+            (_, v) => {
+                base.insert(k, v);
+            }
+        }
+    }
+}
+
+// the flags given on the command line
+fn flags(mm: &ArgMatches) -> io::Result<Table> {
+    let defaults = table(&Config::default())?;
+    let mut layer = Table::new();
+    let single = [
+        ("port", "port"),
+        ("bind", "bind"),
+        ("symlinks", "symlinks"),
+        ("debounce", "debounce"),
+        ("write_token", "write_token"),
+        ("attachments", "attachments"),
+        ("index_dir", "index_dir"),
+        ("tls_cert", "tls_cert"),
+        ("tls_key", "tls_key"),
+        ("http_port", "http_port"),
+    ];
+    for (flag, key) in single.iter() {
+        if let Some(v) = mm.value_of(flag) {
+            let value =
+                typed(v, &defaults[*key]).map_err(|e| invalid(format!("{}: {}", flag, e)))?;
+            layer.insert(key.to_string(), value);
+        }
+    }
+    if let Some(folders) = mm.values_of("folder") {
+        let folders = folders.map(|f| Value::String(f.into())).collect();
+        layer.insert("folders".into(), Value::Array(folders));
+    }
+    if mm.is_present("no_browser") {
+        layer.insert("open_browser".into(), Value::Boolean(false));
+    }
+    if mm.is_present("self_signed") {
+        layer.insert("self_signed".into(), Value::Boolean(true));
+    }
+    if mm.is_present("dev_mode") {
+        layer.insert("dev".into(), Value::Boolean(true));
+    }
+    Ok(layer)
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn layers() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let user = dir.path().join("config.toml");
+        fs::write(
+            &user,
+            "port = 7000\nlog_level = \"info\"\nfolders = [\"notes=n\"]\n[tags]\nlowercase = true\n",
+        )?;
+        fs::create_dir(dir.path().join("n"))?;
+        fs::write(
+            dir.path().join("n").join(FILE_NAME),
+            "port = 7001\ntls_cert = \"cert.pem\"\nignore = [\"drafts\"]\n[tags.synonyms]\nml = \"machine-learning\"\n",
+        )?;
+        let vars = vec![
+            ("KNOWLEDGE_DEBOUNCE".to_string(), "50".to_string()),
+            ("KNOWLEDGE_TAGS_SEPARATOR".into(), "-".into()),
+            (
+                "KNOWLEDGE_CORS_ORIGINS".into(),
+                "http://a.org, http://b.org".into(),
+            ),
+            ("KNOWLEDGE_OPEN_BROWSER".into(), "no".into()),
+            ("HOME".into(), "/home/me".into()),
+        ];
+        let mut flags = Table::new();
+        flags.insert("debounce".into(), Value::Integer(10));
+
+        let (c, sources) = load_from(flags, vars.into_iter(), Some(&user))?;
+        assert_eq!(
+            vec![dir.path().join("n").display().to_string()],
+            c.roots()?
+                .iter()
+                .map(|r| r.path.display().to_string())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("notes", c.roots()?[0].name);
+        assert_eq!(7001, c.port);
+        assert_eq!(10, c.debounce);
+        assert_eq!("info", c.log_level);
+        assert!(!c.open_browser);
+        assert_eq!(vec!["http://a.org", "http://b.org"], c.cors_origins);
+        assert_eq!(vec!["drafts"], c.ignore);
+        assert_eq!(
+            dir.path().join("n").join("cert.pem").display().to_string(),
+            c.tls_cert
+        );
+        assert!(c.tags.lowercase);
+        assert_eq!("-", c.tags.separator);
+        assert_eq!("machine-learning", c.tags.synonyms["ml"]);
+        let root_file = dir.path().join("n").join(FILE_NAME);
+        assert_eq!(
+            vec![
+                "defaults".to_string(),
+                user.display().to_string(),
+                root_file.display().to_string(),
+                "env".into(),
+                "flags".into()
+            ],
+            sources
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn vaults() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let user = dir.path().join("config.toml");
+        fs::write(
+            &user,
+            "[vaults.work]\nfolders = [\"w\"]\nindex_dir = \"idx\"\n\
+             [vaults.home]\nfolders = [\"notes=h\"]\n",
+        )?;
+        let (c, _) = load_from(Table::new(), vec![].into_iter(), Some(&user))?;
+        let vaults = c.vaults()?;
+        assert_eq!(
+            vec!["/home", "/work"],
+            vaults.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>()
+        );
+        let home = &vaults[0].1;
+        assert_eq!("notes", home.roots()?[0].name);
+        assert_eq!(dir.path().join("h"), home.roots()?[0].path);
+        assert_eq!(
+            dir.path().join("idx").display().to_string(),
+            vaults[1].1.index_dir
+        );
+        assert_eq!(c.debounce, home.debounce);
+
+        // a single one at the root without [vaults]
+        let single = Config::default().vaults()?;
+        assert_eq!(1, single.len());
+        assert_eq!("", single[0].0);
+
+        let with = |name: &str, folders: Vec<String>| {
+            let mut c = Config::default();
+            c.vaults.insert(
+                name.into(),
+                VaultConfig {
+                    folders,
+                    ..VaultConfig::default()
+                },
+            );
+            c.vaults()
+        };
+        assert!(with("a/b", vec!["x".into()]).is_err());
+        assert!(with("", vec!["x".into()]).is_err());
+        assert!(with("a", vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn listen() -> std::io::Result<()> {
+        let with = |bind: &str, port, dev| Config {
+            bind: bind.into(),
+            port,
+            dev,
+            ..Config::default()
+        };
+        let tcp = |h: &str, p| Listen::Tcp(h.into(), p);
+        assert_eq!(
+            tcp("0.0.0.0", 7070),
+            with("0.0.0.0:7070", 80, true).listen()?
+        );
+        assert_eq!(tcp("localhost", 80), with("localhost", 80, false).listen()?);
+        assert_eq!(
+            tcp("localhost", 8080),
+            with("localhost", 80, true).listen()?
+        );
+        assert_eq!(tcp("[::1]", 7070), with("[::1]:7070", 0, false).listen()?);
+        assert_eq!(tcp("::1", 80), with("::1", 80, false).listen()?);
+        assert_eq!(
+            Listen::Unix("/run/k.sock".into()),
+            with("unix:/run/k.sock", 0, false).listen()?
+        );
+        assert!(with("localhost:x", 0, false).listen().is_err());
+        match with("localhost", 0, false).listen()? {
+            Listen::Tcp(_, p) => assert!((3000..10000).contains(&p)),
+            l => panic!("{:?}", l),
+        }
+
+        assert_eq!(
+            Some("http://localhost:7070".to_string()),
+            tcp("0.0.0.0", 7070).url(false)
+        );
+        assert_eq!(
+            Some("https://kb.lan:7070".to_string()),
+            tcp("kb.lan", 7070).url(true)
+        );
+        assert_eq!(None, Listen::Unix("/run/k.sock".into()).url(true));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_values() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let user = dir.path().join("config.toml");
+        let load =
+            |vars: Vec<(String, String)>| load_from(Table::new(), vars.into_iter(), Some(&user));
+
+        fs::write(&user, "prot = 7000\n")?;
+        assert!(load(vec![]).is_err()); // typo
+        fs::write(&user, "")?;
+        assert!(load(vec![("KNOWLEDGE_PORT".into(), "a".into())]).is_err());
+        assert!(load(vec![("KNOWLEDGE_PORT".into(), "70000".into())]).is_err());
+
+        let (c, _) = load(vec![("KNOWLEDGE_WRITE_TOKEN".into(), "secret".into())])?;
+        assert!(!c.show()?.contains("secret"));
+        assert!(c.show()?.contains("debounce = 200"));
+
+        let tls = |cert: &str, key: &str, http_port| Config {
+            tls_cert: cert.into(),
+            tls_key: key.into(),
+            http_port,
+            ..Config::default()
+        };
+        assert!(tls("", "", 0).tls()?.is_none());
+        assert!(tls("cert.pem", "", 0).tls().is_err());
+        assert!(tls("", "", 8080).tls().is_err());
+        assert!(tls("nope.pem", "nope.pem", 8080).tls().is_err());
+
+        let folders = Config {
+            folders: vec!["notes=a".into(), "notes=b".into()],
+            ..Config::default()
+        };
+        assert!(folders.roots().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,416 @@
+// an index kept on disk, for the vaults too large to be held in memory ; it survives the
+// restarts, the articles removed meanwhile are forgotten at startup (Store::forget_missing)
+// and the unchanged files aren't read again (Store::unchanged).
+// A sled database : one tree for the articles and one for what was parsed, keyed by path,
+// and one tree per index (tags, ids, roots and wiki names) whose keys are the indexed key
+// then the path of an article ; a change of an article is one transaction over them.
+// Only the articles and what was parsed of them are on disk : the similarity, the link
+// graph and the tag co-occurrences are derived from them in memory (see Store)
+use crate::domain::{ArtRef, TaggedArticle};
+use crate::storage::{Backend, Parsed};
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::transaction::{ConflictableTransactionResult, TransactionalTree};
+use sled::Transactional;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// the files of another layout are left aside, the index is read again from the articles
+static LAYOUT: &str = "v3";
+
+// the trees of the database, by index
+static TREES: [&str; 6] = ["articles", "parsed", "tags", "ids", "roots", "names"];
+const ARTICLES: usize = 0;
+const PARSED: usize = 1;
+const TAGS: usize = 2;
+const IDS: usize = 3;
+const ROOTS: usize = 4;
+const NAMES: usize = 5;
+
+#[derive(Debug)]
+pub struct DiskBackend {
+    db: sled::Db,
+    trees: Vec<sled::Tree>,
+}
+
+impl DiskBackend {
+    // the folder is used by one process at a time
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let dir = dir.join(LAYOUT);
+        let db = sled::open(&dir).map_err(|e| io_error(&dir, e))?;
+        let trees = TREES
+            .iter()
+            .map(|t| db.open_tree(t))
+            .collect::<sled::Result<_>>()
+            .map_err(|e| io_error(&dir, e))?;
+        Ok(DiskBackend { db, trees })
+    }
+
+    // the paths indexed under key
+    fn members(&self, index: usize, key: &str) -> Vec<PathBuf> {
+        let prefix = entry_prefix(key);
+        self.trees[index]
+            .scan_prefix(&prefix)
+            .keys()
+            .filter_map(|k| k.map_err(|e| error!("{}", e)).ok())
+            .map(|k| path(&k[prefix.len()..]))
+            .collect()
+    }
+
+    // each key once : the entries of a key are skipped over
+    fn keys(&self, index: usize) -> Vec<String> {
+        let mut keys = vec![];
+        let mut from = vec![];
+        while let Some(entry) = self.trees[index].range(from.clone()..).keys().next() {
+            let key = match entry.map(|e| entry_key(&e)) {
+                Ok(Some(key)) => key,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("{}", e);
+                    break;
+                }
+            };
+            from = entry_prefix(&key);
+            // past every entry of the key : its last byte is never 0xff (utf-8 or a length)
+            if let Some(last) = from.last_mut() {
+                *last += 1;
+            }
+            keys.push(key);
+        }
+        keys
+    }
+
+    fn articles(&self, paths: Vec<PathBuf>) -> Vec<TaggedArticle> {
+        paths.iter().filter_map(|p| self.get_by_path(p)).collect()
+    }
+
+    // the writes of f happen all together or not at all
+    fn transaction<T>(
+        &self,
+        f: impl Fn(&[TransactionalTree]) -> ConflictableTransactionResult<T, ()>,
+    ) -> Option<T> {
+        self.trees[..]
+            .transaction(|trees| f(trees))
+            .map_err(|e| error!("{:?}", e))
+            .ok()
+    }
+}
+
+// the indexes an article is in
+fn entries(m: &TaggedArticle) -> BTreeSet<(usize, String)> {
+    let mut entries: BTreeSet<_> = m.tags.iter().map(|t| (TAGS, t.clone())).collect();
+    entries.insert((IDS, m.art.id.clone()));
+    entries.insert((ROOTS, m.art.root.clone()));
+    entries.extend(m.art.names().into_iter().map(|n| (NAMES, n)));
+    entries
+}
+
+impl Backend for DiskBackend {
+    fn insert(&self, m: &TaggedArticle) {
+        let value = match encode(m) {
+            Some(value) => value,
+            None => return,
+        };
+        let p = key(&m.art.path);
+        self.transaction(|trees| {
+            let old = trees[ARTICLES]
+                .insert(p.as_slice(), value.as_slice())?
+                .and_then(|old| decode::<TaggedArticle>(&old))
+                .map(|old| entries(&old))
+                .unwrap_or_default();
+            let new = entries(m);
+            for (index, k) in old.difference(&new) {
+                trees[*index].remove(entry(k, &p))?;
+            }
+            for (index, k) in new.difference(&old) {
+                trees[*index].insert(entry(k, &p), &[])?;
+            }
+            Ok(())
+        });
+    }
+
+    fn remove(&self, p: &Path) -> Option<TaggedArticle> {
+        let k = key(p);
+        self.transaction(|trees| {
+            let old = match trees[ARTICLES].remove(k.as_slice())? {
+                Some(old) => decode::<TaggedArticle>(&old),
+                None => return Ok(None),
+            };
+            trees[PARSED].remove(k.as_slice())?;
+            for (index, key) in old.iter().flat_map(entries) {
+                trees[index].remove(entry(&key, &k))?;
+            }
+            Ok(old)
+        })
+        .flatten()
+        .map(|mut old| {
+            old.art.path = p.to_path_buf();
+            old
+        })
+    }
+
+    // what was read from s is read again from the new path
+    fn update_path(&self, s: &Path, m: &TaggedArticle) {
+        let value = match encode(m) {
+            Some(value) => value,
+            None => return,
+        };
+        let (s, d) = (key(s), key(&m.art.path));
+        self.transaction(|trees| {
+            if let Some(old) = trees[ARTICLES].remove(s.as_slice())? {
+                for (index, key) in decode::<TaggedArticle>(&old).iter().flat_map(entries) {
+                    trees[index].remove(entry(&key, &s))?;
+                }
+            }
+            trees[PARSED].remove(s.as_slice())?;
+            trees[ARTICLES].insert(d.as_slice(), value.as_slice())?;
+            for (index, key) in entries(m) {
+                trees[index].insert(entry(&key, &d), &[])?;
+            }
+            Ok(())
+        });
+    }
+
+    fn get_by_path(&self, p: &Path) -> Option<TaggedArticle> {
+        let value = self.trees[ARTICLES]
+            .get(key(p))
+            .map_err(|e| error!("{}", e))
+            .ok()??;
+        let mut m: TaggedArticle = decode(&value)?;
+        m.art.path = p.to_path_buf();
+        Some(m)
+    }
+
+    // the first path when the ids collide, like in memory
+    fn get_by_id(&self, id: &str) -> Option<TaggedArticle> {
+        let paths: BTreeSet<PathBuf> = self.members(IDS, id).into_iter().collect();
+        paths.iter().find_map(|p| self.get_by_path(p))
+    }
+
+    fn get_all_articles(&self) -> Vec<TaggedArticle> {
+        self.trees[ARTICLES]
+            .iter()
+            .filter_map(|kv| kv.map_err(|e| error!("{}", e)).ok())
+            .filter_map(|(k, v)| {
+                let mut m: TaggedArticle = decode(&v)?;
+                m.art.path = path(&k);
+                Some(m)
+            })
+            .collect()
+    }
+
+    // the keys only, no article is read
+    fn get_all_paths(&self) -> Vec<PathBuf> {
+        self.trees[ARTICLES]
+            .iter()
+            .keys()
+            .filter_map(|k| k.map_err(|e| error!("{}", e)).ok())
+            .map(|k| path(&k))
+            .collect()
+    }
+
+    // by path, the order they were added in isn't kept
+    fn get_by_tag(&self, tag: &str) -> Vec<ArtRef> {
+        let mut paths = self.members(TAGS, tag);
+        paths.sort();
+        self.articles(paths).into_iter().map(|m| m.art).collect()
+    }
+
+    fn get_all_tags(&self) -> Vec<String> {
+        self.keys(TAGS)
+    }
+
+    fn get_by_root(&self, root: &str) -> Vec<ArtRef> {
+        let paths = self.members(ROOTS, root);
+        self.articles(paths).into_iter().map(|m| m.art).collect()
+    }
+
+    fn get_by_name(&self, target: &str) -> Vec<TaggedArticle> {
+        let mut found = self.articles(self.members(NAMES, target));
+        found.retain(|m| m.art.is_named(target));
+        found
+    }
+
+    fn set_parsed(&self, p: &Path, parsed: &Parsed) {
+        if let Some(value) = encode(parsed) {
+            if let Err(e) = self.trees[PARSED].insert(key(p), value) {
+                error!("{}: {}", p.display(), e);
+            }
+        }
+    }
+
+    fn get_parsed(&self, p: &Path) -> Option<Parsed> {
+        let value = self.trees[PARSED]
+            .get(key(p))
+            .map_err(|e| error!("{}", e))
+            .ok()??;
+        let mut parsed: Parsed = decode(&value)?;
+        parsed.meta.art.path = p.to_path_buf();
+        Some(parsed)
+    }
+}
+
+impl Drop for DiskBackend {
+    fn drop(&mut self) {
+        if let Err(e) = self.db.flush() {
+            error!("{}", e);
+        }
+    }
+}
+
+// the path of an article isn't serialized with it (see ArtRef), it's the key
+fn key(p: &Path) -> Vec<u8> {
+    p.to_string_lossy().as_bytes().to_vec()
+}
+
+fn path(key: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(key).as_ref())
+}
+
+// the length of the key first : no key is the prefix of another
+fn entry_prefix(key: &str) -> Vec<u8> {
+    let mut prefix = (key.len() as u32).to_be_bytes().to_vec();
+    prefix.extend_from_slice(key.as_bytes());
+    prefix
+}
+
+fn entry(key: &str, p: &[u8]) -> Vec<u8> {
+    let mut entry = entry_prefix(key);
+    entry.extend_from_slice(p);
+    entry
+}
+
+fn entry_key(entry: &[u8]) -> Option<String> {
+    let (len, rest) = entry.split_at_checked(4)?;
+    let len = u32::from_be_bytes(len.try_into().ok()?) as usize;
+    String::from_utf8(rest.get(..len)?.to_vec()).ok()
+}
+
+fn encode<T: Serialize>(v: &T) -> Option<Vec<u8>> {
+    serde_json::to_vec(v).map_err(|e| error!("{}", e)).ok()
+}
+
+fn decode<T: DeserializeOwned>(v: &[u8]) -> Option<T> {
+    serde_json::from_slice(v).map_err(|e| error!("{}", e)).ok()
+}
+
+fn io_error(dir: &Path, e: sled::Error) -> io::Error {
+    io::Error::other(format!("{}: {}", dir.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Root, SymlinkPolicy};
+    use crate::storage::Store;
+    use crate::uc;
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    #[test]
+    fn survives_restarts() -> std::io::Result<()> {
+        let vault = tempdir()?;
+        let index = tempdir()?;
+        let kept = vault.path().join("kept.md");
+        let gone = vault.path().join("gone.md");
+        fs::write(&kept, "")?;
+
+        let open = || -> std::io::Result<Store> {
+            let s = Store::with_backend(DiskBackend::open(index.path())?);
+            s.add_root(vault.path(), "vault");
+            Ok(s)
+        };
+        {
+            let s = open()?;
+            s.insert(&TaggedArticle::new(kept.clone(), "kept", &["a".into()]));
+            s.insert(&TaggedArticle::new(gone.clone(), "gone", &["a".into()]));
+        }
+
+        let s = open()?;
+        assert_eq!(2, s.get_by_tag("a").len());
+        assert_eq!(2, s.tag_counts()[0].count);
+        // removed while the app wasn't running
+        s.forget_missing();
+        assert_eq!(
+            vec![kept.clone()],
+            s.get_by_tag("a")
+                .into_iter()
+                .map(|a| a.path)
+                .collect::<Vec<_>>()
+        );
+        assert!(s.get_by_path(&gone).is_none());
+        assert_eq!(vec!["a".to_string()], s.get_all_tags());
+        assert_eq!(1, s.tag_counts()[0].count);
+        Ok(())
+    }
+
+    #[test]
+    fn keys_apart() -> std::io::Result<()> {
+        let index = tempdir()?;
+        let backend = DiskBackend::open(index.path())?;
+        let a = TaggedArticle::new("/v/a.md".into(), "a", &["r".into(), "rust".into()]);
+        let b = TaggedArticle::new("/v/b.md".into(), "b", &["rust".into(), "".into()]);
+        backend.insert(&a);
+        backend.insert(&b);
+
+        let mut tags = backend.get_all_tags();
+        tags.sort();
+        assert_eq!(vec!["", "r", "rust"], tags);
+        assert_eq!(vec![a.art.clone()], backend.get_by_tag("r"));
+        assert_eq!(2, backend.get_by_tag("rust").len());
+
+        backend.update_path(
+            &a.art.path,
+            &TaggedArticle::new("/v/c.md".into(), "a", &a.tags),
+        );
+        assert!(backend.get_by_path(&a.art.path).is_none());
+        assert_eq!(Path::new("/v/c.md"), backend.get_by_tag("r")[0].path);
+        assert_eq!(Some(b.clone()), backend.remove(&b.art.path));
+        assert_eq!(vec!["r", "rust"], backend.get_all_tags());
+        Ok(())
+    }
+
+    #[test]
+    fn unchanged_files_kept() -> std::io::Result<()> {
+        let vault = tempdir()?;
+        let index = tempdir()?;
+        let a = vault.path().join("a.md");
+        fs::write(&a, "---\ntitle: a\ntags: [old]\n---\n")?;
+        let was = SystemTime::now() - Duration::from_secs(60);
+        File::options().write(true).open(&a)?.set_modified(was)?;
+
+        let scan = || -> std::io::Result<Store> {
+            let s = Store::with_backend(DiskBackend::open(index.path())?);
+            let roots = [Root::new(vault.path().to_path_buf())];
+            uc::scan_into(&s, &roots, SymlinkPolicy::Follow);
+            Ok(s)
+        };
+        assert_eq!(1, scan()?.get_by_tag("old").len());
+
+        // same date : not read again
+        fs::write(&a, "---\ntitle: a\ntags: [new]\n---\n")?;
+        File::options().write(true).open(&a)?.set_modified(was)?;
+        let s = scan()?;
+        assert_eq!(1, s.get_by_tag("old").len());
+        assert!(s.get_by_tag("new").is_empty());
+        drop(s); // one process at a time
+
+        File::options()
+            .write(true)
+            .open(&a)?
+            .set_modified(SystemTime::now())?;
+        let s = scan()?;
+        assert!(s.get_by_tag("old").is_empty());
+        assert_eq!(1, s.get_by_tag("new").len());
+        assert_eq!(vec!["new".to_string()], s.get_all_tags());
+        drop(s);
+        // the entry of the old tag went with its last article
+        let backend = DiskBackend::open(index.path())?;
+        assert_eq!(1, backend.trees[TAGS].len());
+        Ok(())
+    }
+}
@@ -1,17 +1,10 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::hash::Hash;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-#[derive(Debug)]
-pub enum MetadataEvent {
-    Create(TaggedArticle),
-    Move(PathBuf, PathBuf),
-    Remove(PathBuf),
-    Changed(TaggedArticle),
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, PartialOrd, Ord)]
 pub struct TaggedArticle {
     pub art: ArtRef,
     pub tags: Vec<String>,
@@ -32,10 +25,28 @@ impl TaggedArticle {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, PartialOrd, Ord)]
 pub struct ArtRef {
+    #[serde(skip_serializing, default)] // the clients use the id, not where the file lives
     pub path: PathBuf,
     pub title: String,
+    pub root: String,      // name of the root folder the article was found in
+    pub id: String,        // the front matter `id:`, or the path relative to the root
+    pub rel_path: PathBuf, // relative to the root folder
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>, // from the front matter
+}
+
+impl AsRef<ArtRef> for ArtRef {
+    fn as_ref(&self) -> &ArtRef {
+        self
+    }
+}
+
+impl AsRef<ArtRef> for TaggedArticle {
+    fn as_ref(&self) -> &ArtRef {
+        &self.art
+    }
 }
 
 impl ArtRef {
@@ -43,6 +54,127 @@ impl ArtRef {
         ArtRef {
             path,
             title: title.to_owned(),
+            root: String::new(),
+            id: String::new(),
+            rel_path: PathBuf::new(),
+            aliases: vec![],
+        }
+    }
+
+    // what a wiki link to target (lowercased, without ".md") may lead to : the same title,
+    // file name or alias, a target like "folder/name" matches the end of the path
+    pub fn is_named(&self, target: &str) -> bool {
+        let stem = self.rel_path.with_extension("");
+        let stem = stem.to_string_lossy().to_lowercase();
+        stem == target
+            || stem.ends_with(&format!("/{}", target))
+            || self.title.to_lowercase() == target
+            || self.aliases.iter().any(|a| a.to_lowercase() == target)
+    }
+
+    // the targets is_named accepts : the title, the aliases and the ends of the path
+    pub fn names(&self) -> BTreeSet<String> {
+        let stem = self.rel_path.with_extension("");
+        let stem = stem.to_string_lossy().to_lowercase();
+        let mut names: BTreeSet<String> = stem
+            .match_indices('/')
+            .map(|(i, _)| stem[i + 1..].to_string())
+            .collect();
+        names.insert(stem.clone());
+        names.insert(self.title.to_lowercase());
+        names.extend(self.aliases.iter().map(|a| a.to_lowercase()));
+        names
+    }
+}
+
+// an entry of the table of contents of an article
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Heading {
+    pub level: u32,
+    pub text: String,
+    pub slug: String, // the anchor of the heading in the rendered article
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize, // of articles
+}
+
+// a tag often found along another one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RelatedTag {
+    pub tag: String,
+    pub count: usize, // of articles bearing both
+    pub lift: f64,    // how much more than by chance, 1 : independent
+    pub pmi: f64,     // log2 of the lift
+}
+
+// an article close to another one, the scores go from 0 to 1
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RelatedArticle {
+    #[serde(flatten)]
+    pub art: ArtRef,
+    pub score: f64, // the weighted sum of the three others
+    pub text: f64,  // cosine of their tf-idf vectors
+    pub tags: f64,  // shared tags, over all their tags
+    pub links: f64, // 1 when one links to the other, else their shared link targets
+}
+
+// a relative link of an article (or an anchor in it)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Link {
+    pub dest: String,       // as written
+    pub id: Option<String>, // the article it leads to
+    pub anchor: Option<String>,
+    pub valid: bool, // the target and its anchor exist
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<String>, // the articles an ambiguous wiki link could lead to
+}
+
+// a folder indexed in the knowledge base
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Root {
+    // the folder name is used when no name is given
+    pub fn new(path: PathBuf) -> Self {
+        let name = match path.file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => path.to_string_lossy().to_string(),
+        };
+        Root { name, path }
+    }
+}
+
+// the roots are told apart by their names (ids, `root:` queries, mounts)
+pub fn check_root_names(roots: &[Root]) -> Result<(), String> {
+    let mut names = HashSet::new();
+    match roots.iter().find(|r| !names.insert(&r.name)) {
+        Some(r) => Err(format!("several root folders are named {}", r.name)),
+        None => Ok(()),
+    }
+}
+
+// either "path" or "name=path"
+impl FromStr for Root {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty root folder".into());
+        }
+
+        match s.split_once('=') {
+            Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok(Root {
+                name: name.into(),
+                path: path.into(),
+            }),
+            Some(_) => Err(format!("invalid root folder: {}", s)),
+            None => Ok(Root::new(s.into())),
         }
     }
 }
@@ -74,22 +206,22 @@ impl<A: Hash + Eq + Clone> Exp<A> {
         match self {
             Exp::Sing(a) => a.clone(),
             Exp::Comb(op, a, b) => match op {
-                Op::Or => a.union(&b),
-                Op::And => a.inter(&b),
+                Op::Or => a.union(b),
+                Op::And => a.inter(b),
             },
         }
     }
 
     fn inter(&self, b: &Exp<A>) -> HashSet<A> {
         match (&self, b) {
-            (Exp::Sing(x), Exp::Sing(y)) => x.intersection(&y).cloned().collect(),
+            (Exp::Sing(x), Exp::Sing(y)) => x.intersection(y).cloned().collect(),
             _ => Exp::inter(&Exp::Sing(self.reduce()), &Exp::Sing(b.reduce())),
         }
     }
 
     fn union(&self, b: &Exp<A>) -> HashSet<A> {
         match (&self, b) {
-            (Exp::Sing(x), Exp::Sing(y)) => x.union(&y).cloned().collect(),
+            (Exp::Sing(x), Exp::Sing(y)) => x.union(y).cloned().collect(),
             _ => Exp::union(&Exp::Sing(self.reduce()), &Exp::Sing(b.reduce())),
         }
     }
@@ -157,6 +289,70 @@ mod tests {
         assert_eq!(to_hash(vec![1, 2, 3]), a.reduce());
         Ok(())
     }
+
+    #[test]
+    fn rules() -> std::io::Result<()> {
+        let ignore =
+            IgnoreRules::new(&["drafts".into(), "*.tmp.md".into(), "/sub/private".into()]).unwrap();
+        assert!(ignore.ignores(Path::new("drafts")));
+        assert!(ignore.ignores(Path::new("a/drafts/b.md")));
+        assert!(ignore.ignores(Path::new("a/b.tmp.md")));
+        assert!(ignore.ignores(Path::new("sub/private/c/d.md")));
+        assert!(!ignore.ignores(Path::new("private/d.md")));
+        assert!(!ignore.ignores(Path::new("a/b.md")));
+        assert!(IgnoreRules::new(&["[".into()]).is_err());
+
+        let tags = TagRules {
+            lowercase: true,
+            separator: "-".into(),
+            synonyms: vec![("ml".to_string(), "machine-learning".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let raw = vec![
+            "ML".into(),
+            "Machine  learning".into(),
+            "Rust".into(),
+            " ".into(),
+        ];
+        assert_eq!(vec!["machine-learning", "rust"], tags.normalize(&raw));
+        assert_eq!(
+            vec!["ML", "Machine  learning", "Rust"],
+            TagRules::default().normalize(&raw)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn root_from_str() -> std::io::Result<()> {
+        assert_eq!(
+            Ok(Root {
+                name: "wiki".into(),
+                path: "/home/me/wiki".into()
+            }),
+            "/home/me/wiki".parse()
+        );
+        assert_eq!(
+            Ok(Root {
+                name: "team".into(),
+                path: "/home/me/wiki".into()
+            }),
+            "team=/home/me/wiki".parse()
+        );
+        assert!("=/home/me/wiki".parse::<Root>().is_err());
+        assert!("".parse::<Root>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn no_path_in_json() -> std::io::Result<()> {
+        let mut art = ArtRef::new("/home/me/wiki/a.md".into(), "a");
+        art.id = "a".into();
+        let json = serde_json::to_value(&art)?;
+        assert!(json.get("path").is_none());
+        assert_eq!("a", json["id"]);
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -173,3 +369,105 @@ pub enum FileOp {
     Write,
     Move,
 }
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    Ignore,
+    Follow,     // with cycle detection
+    FollowOnce, // symlinks found behind a symlink are ignored
+}
+
+impl SymlinkPolicy {
+    // links: number of symlinks already followed to reach the entry
+    pub fn follows(self, links: u8) -> bool {
+        match self {
+            SymlinkPolicy::Ignore => false,
+            SymlinkPolicy::Follow => true,
+            SymlinkPolicy::FollowOnce => links == 0,
+        }
+    }
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(SymlinkPolicy::Ignore),
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "follow-once" => Ok(SymlinkPolicy::FollowOnce),
+            _ => Err(format!("unknown symlink policy: {}", s)),
+        }
+    }
+}
+
+// what is indexed, and how
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    pub ignore: IgnoreRules,
+    pub tags: TagRules,
+}
+
+// gitignore like : a pattern without '/' matches any file or folder name, one with '/'
+// matches the path from the root, what's below a matching folder is ignored too
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules(Vec<glob::Pattern>);
+
+impl IgnoreRules {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        patterns
+            .iter()
+            .map(|p| {
+                glob::Pattern::new(p.trim_matches('/'))
+                    .map_err(|e| format!("invalid ignore pattern {}: {}", p, e))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(IgnoreRules)
+    }
+
+    // rel : relative to the root
+    pub fn ignores(&self, rel: &Path) -> bool {
+        self.0.iter().any(|p| {
+            if p.as_str().contains('/') {
+                rel.ancestors().any(|a| p.matches_path(a))
+            } else {
+                rel.iter().any(|c| p.matches(&c.to_string_lossy()))
+            }
+        })
+    }
+}
+
+// how the tags of the headers are indexed, none of it is written back to the files
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TagRules {
+    pub lowercase: bool,
+    pub separator: String, // replaces the whitespaces when not empty
+    pub synonyms: BTreeMap<String, String>, // applied last, eg: ml = "machine-learning"
+}
+
+impl TagRules {
+    pub fn normalize(&self, tags: &[String]) -> Vec<String> {
+        let mut out: Vec<String> = vec![];
+        for t in tags {
+            let mut t = t.trim().to_string();
+            if self.lowercase {
+                t = t.to_lowercase();
+            }
+            if !self.separator.is_empty() {
+                t = t
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(&self.separator);
+            }
+            if let Some(s) = self.synonyms.get(&t) {
+                t = s.clone();
+            }
+            if !t.is_empty() && !out.contains(&t) {
+                out.push(t);
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,220 @@
+use crate::article_writer;
+use crate::domain::{FileEvent, FileOp};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// editors saving through temp files (vim, jetbrains...) produce storms of
+// create/remove/rename : a burst is reduced to its net effect for each path,
+// and the editor's own files are dropped
+pub fn coalesce(events: Vec<FileEvent>) -> Vec<FileEvent> {
+    let events: Vec<FileEvent> = events.into_iter().filter_map(drop_editor_files).collect();
+
+    let mut count: HashMap<PathBuf, usize> = HashMap::new();
+    for e in &events {
+        for p in e.dst.iter().chain(std::iter::once(&e.path)) {
+            *count.entry(p.clone()).or_insert(0) += 1;
+        }
+    }
+
+    // lone moves don't touch any other path of the burst, they are kept as is
+    let mut net: Vec<FileEvent> = vec![];
+    let mut states = States::default();
+
+    for e in events {
+        match (&e.op, &e.dst) {
+            (FileOp::Move, Some(dst)) if count[&e.path] == 1 && count[dst] == 1 => net.push(e),
+            (FileOp::Move, Some(dst)) => {
+                states.update(&e.path, true, false);
+                states.update(dst, true, true);
+            }
+            (FileOp::Move, None) => {}
+            (FileOp::Create, _) => states.update(&e.path, false, true),
+            (FileOp::Write, _) => states.update(&e.path, true, true),
+            (FileOp::Remove, _) => states.update(&e.path, true, false),
+        }
+    }
+
+    net.extend(states.order.iter().filter_map(|p| {
+        let op = match states.by_path[p] {
+            State {
+                existed: true,
+                exists: true,
+            } => FileOp::Write,
+            State {
+                existed: true,
+                exists: false,
+            } => FileOp::Remove,
+            State {
+                existed: false,
+                exists: true,
+            } => FileOp::Create,
+            State {
+                existed: false,
+                exists: false,
+            } => return None,
+        };
+        Some(FileEvent {
+            op,
+            path: p.clone(),
+            dst: None,
+        })
+    }));
+    net
+}
+
+#[derive(Default)]
+struct States {
+    order: Vec<PathBuf>, // paths by first appearance
+    by_path: HashMap<PathBuf, State>,
+}
+
+impl States {
+    fn update(&mut self, p: &Path, existed: bool, exists: bool) {
+        let order = &mut self.order;
+        let st = self.by_path.entry(p.to_path_buf()).or_insert_with(|| {
+            order.push(p.to_path_buf());
+            State { existed, exists }
+        });
+        st.exists = exists;
+    }
+}
+
+struct State {
+    existed: bool, // before the burst
+    exists: bool,  // after the burst
+}
+
+fn drop_editor_files(e: FileEvent) -> Option<FileEvent> {
+    let src_is_tmp = is_editor_file(&e.path);
+    match e.dst {
+        Some(dst) if e.op == FileOp::Move => match (src_is_tmp, is_editor_file(&dst)) {
+            (false, false) => Some(FileEvent {
+                dst: Some(dst),
+                ..e
+            }),
+            // the temp file replaces the article
+            (true, false) => Some(FileEvent {
+                op: FileOp::Write,
+                path: dst,
+                dst: None,
+            }),
+            // the article is moved away as a backup
+            (false, true) => Some(FileEvent {
+                op: FileOp::Remove,
+                path: e.path,
+                dst: None,
+            }),
+            (true, true) => None,
+        },
+        _ if src_is_tmp => None,
+        dst => Some(FileEvent { dst, ..e }),
+    }
+}
+
+// swap, backup and temp files of the usual editors (and ours)
+pub fn is_editor_file(p: &Path) -> bool {
+    let name = match p.file_name() {
+        Some(n) => n.to_string_lossy(),
+        None => return false,
+    };
+
+    name == "4913" // vim checks it can write in the folder
+        || name.ends_with('~')
+        || name.ends_with(".swp")
+        || name.ends_with(".swo")
+        || name.ends_with(".swx")
+        || name.ends_with("___jb_tmp___")
+        || name.ends_with("___jb_old___")
+        || name.ends_with(article_writer::TMP_SUFFIX) // our own atomic writes
+        || name.starts_with(".#") // emacs lock
+        || (name.starts_with('#') && name.ends_with('#')) // emacs autosave
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ev(op: FileOp, p: &str) -> FileEvent {
+        FileEvent {
+            op,
+            path: p.into(),
+            dst: None,
+        }
+    }
+
+    fn mv(src: &str, dst: &str) -> FileEvent {
+        FileEvent {
+            op: FileOp::Move,
+            path: src.into(),
+            dst: Some(dst.into()),
+        }
+    }
+
+    #[test]
+    fn vim_save() -> std::io::Result<()> {
+        let storm = vec![
+            ev(FileOp::Create, "/n/4913"),
+            ev(FileOp::Remove, "/n/4913"),
+            mv("/n/a.md", "/n/a.md~"),
+            ev(FileOp::Create, "/n/a.md"),
+            ev(FileOp::Write, "/n/a.md"),
+            ev(FileOp::Remove, "/n/a.md~"),
+            ev(FileOp::Write, "/n/.a.md.swp"),
+        ];
+        assert_eq!(vec![ev(FileOp::Write, "/n/a.md")], coalesce(storm));
+        Ok(())
+    }
+
+    #[test]
+    fn jetbrains_save() -> std::io::Result<()> {
+        let storm = vec![
+            ev(FileOp::Create, "/n/a.md___jb_tmp___"),
+            ev(FileOp::Write, "/n/a.md___jb_tmp___"),
+            mv("/n/a.md", "/n/a.md___jb_old___"),
+            mv("/n/a.md___jb_tmp___", "/n/a.md"),
+            ev(FileOp::Remove, "/n/a.md___jb_old___"),
+        ];
+        assert_eq!(vec![ev(FileOp::Write, "/n/a.md")], coalesce(storm));
+        Ok(())
+    }
+
+    #[test]
+    fn net_effects() -> std::io::Result<()> {
+        // transient file
+        assert!(coalesce(vec![
+            ev(FileOp::Create, "/n/a.md"),
+            ev(FileOp::Write, "/n/a.md"),
+            ev(FileOp::Remove, "/n/a.md"),
+        ])
+        .is_empty());
+
+        // new file
+        assert_eq!(
+            vec![ev(FileOp::Create, "/n/a.md")],
+            coalesce(vec![
+                ev(FileOp::Create, "/n/a.md"),
+                ev(FileOp::Write, "/n/a.md"),
+            ])
+        );
+
+        // written then removed
+        assert_eq!(
+            vec![ev(FileOp::Remove, "/n/a.md")],
+            coalesce(vec![
+                ev(FileOp::Write, "/n/a.md"),
+                ev(FileOp::Remove, "/n/a.md"),
+            ])
+        );
+
+        // lone moves are kept, the others split
+        assert_eq!(
+            vec![mv("/n/a.md", "/n/b.md"), ev(FileOp::Write, "/n/c.md")],
+            coalesce(vec![mv("/n/a.md", "/n/b.md"), ev(FileOp::Write, "/n/c.md")])
+        );
+        assert_eq!(
+            vec![ev(FileOp::Remove, "/n/a.md"), ev(FileOp::Write, "/n/b.md")],
+            coalesce(vec![mv("/n/a.md", "/n/b.md"), ev(FileOp::Write, "/n/b.md")])
+        );
+        Ok(())
+    }
+}
@@ -1,109 +1,253 @@
-use async_std::sync::{Receiver, Sender};
+use async_std::sync::{channel, Receiver, Sender};
 use async_std::task;
+use log::warn;
 use path_abs::PathAbs;
-use std::fs::File;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
 use std::io::{prelude::*, BufReader, Error as ioErr, ErrorKind, Lines, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str;
-use yaml_rust::YamlLoader;
+use std::thread;
+use yaml_rust::{Yaml, YamlLoader};
 
-use crate::domain::{FileEvent, FileOp, MetadataEvent, TaggedArticle};
+use crate::domain::{FileEvent, FileOp, TaggedArticle};
+use crate::link_graph::Outline;
+use crate::metadata_handler::MetadataEvent;
+use crate::similarity::Doc;
+use crate::storage::{Parsed, Store};
+
+// events are dispatched on a fixed number of lanes, selected by path :
+// two events about the same file are always handled in the order they were received.
+// There are more lanes than parsers so that the parsers are kept busy
+const LANES_BY_PARSER: usize = 4;
+
+enum Job {
+    Create(PathBuf),
+    Write(PathBuf),
+    Remove(PathBuf),
+    Sync(Sender<()>),
+}
+
+pub fn watch(rch: &Receiver<FileEvent>, metach: &Sender<MetadataEvent>, store: &Store) {
+    let parsers = ParserPool::sized_to_cpus(store);
 
-pub fn watch(rch: &Receiver<FileEvent>, metach: &Sender<MetadataEvent>) {
     task::block_on(async {
-        loop {
-            match rch.recv().await {
-                Ok(file_event) => {
-                    if let Ok(path) = clean_path(file_event.path.clone()) {
-                        match file_event.op {
-                            FileOp::Create => {
-                                let mc = metach.clone();
-                                task::spawn(async move {
-                                    let _ = handle_create(&path, &mc).await;
-                                });
-                            }
-
-                            FileOp::Remove => {
-                                let mc = metach.clone();
-                                task::spawn(async move {
-                                    mc.send(MetadataEvent::Remove(path)).await;
-                                });
-                            }
-
-                            FileOp::Move => {
-                                if let Some(dst) = file_event.dst {
-                                    if let Ok(new_path) = clean_path(dst) {
-                                        let mc = metach.clone();
-                                        let old_path = path.clone();
-                                        task::spawn(async move {
-                                            mc.send(MetadataEvent::Move(old_path, new_path)).await;
-                                        });
-                                    }
-                                } else {
-                                }
-                            }
-
-                            FileOp::Write => {
-                                let mc = metach.clone();
-                                task::spawn(async move {
-                                    let _ = handle_write(&path, &mc).await;
-                                });
-                            }
-                        }
-                    } else {
-                        println!("invalid path {:?}", file_event.path);
-                        continue;
-                    }
-                }
-                Err(e) => {
-                    println!("file handler watch err: {}", e);
-                    continue;
-                }
-            };
+        let lanes: Vec<Sender<Job>> = (0..parsers.workers * LANES_BY_PARSER)
+            .map(|_| {
+                let (job_send, job_rcv) = channel(100);
+                let mc = metach.clone();
+                let pc = parsers.clone();
+                task::spawn(async move { run_lane(&job_rcv, &mc, &pc).await });
+                job_send
+            })
+            .collect();
+
+        // until the watchers and the traversal are gone (see uc::Index::stop)
+        while let Ok(file_event) = rch.recv().await {
+            dispatch(file_event, &lanes, metach).await;
         }
     });
 }
 
+async fn dispatch(file_event: FileEvent, lanes: &[Sender<Job>], metach: &Sender<MetadataEvent>) {
+    let path = match clean_path(file_event.path.clone()) {
+        Ok(path) => path,
+        Err(_) => {
+            warn!("invalid path {:?}", file_event.path);
+            return;
+        }
+    };
+
+    match file_event.op {
+        FileOp::Create => lane(lanes, &path).send(Job::Create(path)).await,
+        FileOp::Write => lane(lanes, &path).send(Job::Write(path)).await,
+        FileOp::Remove => lane(lanes, &path).send(Job::Remove(path)).await,
+        FileOp::Move => {
+            if let Some(Ok(new_path)) = file_event.dst.map(clean_path) {
+                // a move involves two lanes : both are drained before it is applied
+                sync(lane(lanes, &path)).await;
+                sync(lane(lanes, &new_path)).await;
+                metach
+                    .send(MetadataEvent::Move(path, new_path.clone()))
+                    .await;
+
+                // the source may never have been parsed (eg: editor temp file)
+                lane(lanes, &new_path).send(Job::Write(new_path)).await;
+            }
+        }
+    }
+}
+
+fn lane<'a>(lanes: &'a [Sender<Job>], p: &Path) -> &'a Sender<Job> {
+    let mut hasher = DefaultHasher::new();
+    p.hash(&mut hasher);
+    &lanes[(hasher.finish() as usize) % lanes.len()]
+}
+
+async fn sync(l: &Sender<Job>) {
+    let (done_send, done_rcv) = channel(1);
+    l.send(Job::Sync(done_send)).await;
+    let _ = done_rcv.recv().await;
+}
+
+async fn run_lane(jobs: &Receiver<Job>, mc: &Sender<MetadataEvent>, parsers: &ParserPool) {
+    while let Ok(job) = jobs.recv().await {
+        match job {
+            Job::Create(p) => handle_create(&p, mc, parsers).await,
+            Job::Write(p) => handle_write(&p, mc, parsers).await,
+            Job::Remove(p) => mc.send(MetadataEvent::Remove(p)).await,
+            Job::Sync(done) => done.send(()).await,
+        }
+    }
+}
+
+// symlinks are resolved : a file reachable through several paths is indexed once
 fn clean_path(p: PathBuf) -> Result<PathBuf> {
+    if let Ok(real) = fs::canonicalize(&p) {
+        return Ok(real);
+    }
+
+    // removed files can't be resolved anymore, their folder usually can
+    if let (Some(parent), Some(name)) = (p.parent(), p.file_name()) {
+        if let Ok(real_parent) = fs::canonicalize(parent) {
+            return Ok(real_parent.join(name));
+        }
+    }
+
     if let Ok(new_path) = PathAbs::new(p) {
         Ok(new_path.into())
     } else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "invalid path",
-        ))
+        Err(ioErr::other("invalid path"))
     }
 }
 
-async fn handle_write(p: &PathBuf, mc: &Sender<MetadataEvent>) -> Result<()> {
-    let m = match get_metadata(&p).await {
-        Ok(m) => m,
-        Err(e) => return Err(e),
-    };
+// files are read again when handling the event : if it can't be parsed anymore
+// (removed since, or header broken) the article is dropped from the store
 
-    mc.send(MetadataEvent::Changed(m)).await;
-    Ok(())
+async fn handle_write(p: &Path, mc: &Sender<MetadataEvent>, parsers: &ParserPool) {
+    match parsers.parse(p, false).await {
+        Ok(parsed) => mc.send(MetadataEvent::Changed(parsed)).await,
+        Err(_) => mc.send(MetadataEvent::Remove(p.to_path_buf())).await,
+    }
 }
 
-async fn handle_create(p: &PathBuf, mc: &Sender<MetadataEvent>) -> Result<()> {
-    let m = match get_metadata(&p).await {
-        Ok(m) => m,
-        Err(e) => return Err(e),
-    };
+// the traversal reports every file as created : the ones the store already read are
+// taken back when unchanged
+async fn handle_create(p: &Path, mc: &Sender<MetadataEvent>, parsers: &ParserPool) {
+    match parsers.parse(p, true).await {
+        Ok(parsed) => mc.send(MetadataEvent::Create(parsed)).await,
+        Err(_) => mc.send(MetadataEvent::Remove(p.to_path_buf())).await,
+    }
+}
 
-    mc.send(MetadataEvent::Create(m)).await;
-    Ok(())
+// reading the files blocks : it is done by a fixed number of threads,
+// out of the async executor
+#[derive(Clone)]
+struct ParserPool {
+    workers: usize,
+    jobs: Sender<ParseJob>,
 }
 
-async fn get_metadata(e: &PathBuf) -> Result<TaggedArticle> {
-    let file = File::open(e)?;
-    let reader = BufReader::new(file);
+type ParseJob = (PathBuf, bool, Sender<Result<Parsed>>);
+
+impl ParserPool {
+    fn new(workers: usize, store: &Store) -> Self {
+        let (jobs, jobs_rcv): (Sender<ParseJob>, Receiver<ParseJob>) =
+            channel(workers * LANES_BY_PARSER);
+
+        for _ in 0..workers {
+            let jr = jobs_rcv.clone();
+            let store = store.clone();
+            thread::spawn(move || {
+                task::block_on(async {
+                    while let Ok((p, reuse, reply)) = jr.recv().await {
+                        let parsed = if reuse {
+                            parse_changed(&p, &store)
+                        } else {
+                            parse(&p)
+                        };
+                        reply.send(parsed).await;
+                    }
+                })
+            });
+        }
+
+        ParserPool { workers, jobs }
+    }
+
+    fn sized_to_cpus(store: &Store) -> Self {
+        ParserPool::new(
+            thread::available_parallelism().map_or(1, |n| n.get()),
+            store,
+        )
+    }
+
+    // reuse : what the store read from the file is taken back when it is unchanged
+    async fn parse(&self, p: &Path, reuse: bool) -> Result<Parsed> {
+        let (reply, result) = channel(1);
+        self.jobs.send((p.to_path_buf(), reuse, reply)).await;
+        match result.recv().await {
+            Ok(res) => res,
+            Err(_) => Err(ioErr::other("parser stopped")),
+        }
+    }
+}
+
+// the header, then the words, links and headings of the whole file
+pub fn parse(p: &Path) -> Result<Parsed> {
+    // a change made while reading leaves a newer date : the file is read again next time
+    let modified = fs::metadata(p)?.modified().ok();
+    // read once : the header is taken from the same bytes
+    let content = fs::read(p)?;
+    let meta = read_metadata(p, content.as_slice())?;
+    let content = String::from_utf8_lossy(&content);
+    Ok(Parsed {
+        meta,
+        doc: Doc::parse(p, &content),
+        outline: Outline::parse(&content),
+        modified,
+        kept: false,
+    })
+}
+
+// p is expected to be canonical
+pub fn parse_changed(p: &Path, store: &Store) -> Result<Parsed> {
+    match store.unchanged(p) {
+        Some(parsed) => Ok(parsed),
+        None => parse(p),
+    }
+}
+
+pub fn get_metadata(e: &Path) -> Result<TaggedArticle> {
+    read_metadata(e, BufReader::new(File::open(e)?))
+}
+
+// the header read from the file of e, up to its closing delimiter
+fn read_metadata<R: BufRead>(e: &Path, reader: R) -> Result<TaggedArticle> {
     let yaml = get_yaml_header(reader.lines())?;
-    let (title, tags) = yaml_to_meta(&yaml)?;
-    Ok(TaggedArticle::new(e.clone(), &title, &tags))
+    let h = yaml_to_meta(&yaml)?;
+    let mut m = TaggedArticle::new(e.to_path_buf(), &h.title, &h.tags);
+    m.art.id = h.id.unwrap_or_default(); // the default one depends on the root
+    m.art.aliases = h.aliases;
+    Ok(m)
+}
+
+// the yaml header of an article
+#[derive(Debug, PartialEq, Default)]
+pub struct FrontMatter {
+    pub title: String,
+    pub tags: Vec<String>,
+    pub id: Option<String>,
+    pub aliases: Vec<String>, // other names in wiki links
+}
+
+// content : a whole article, or only its header
+pub fn parse_front_matter(content: &str) -> Result<FrontMatter> {
+    yaml_to_meta(&get_yaml_header(content.as_bytes().lines())?)
 }
 
-fn yaml_to_meta(s: &str) -> Result<(String, Vec<String>)> {
+fn yaml_to_meta(s: &str) -> Result<FrontMatter> {
     let docs = match YamlLoader::load_from_str(s) {
         Ok(docs) => docs,
         Err(e) => return Err(ioErr::new(ErrorKind::NotFound, format!("{}", e))),
@@ -128,23 +272,48 @@ fn yaml_to_meta(s: &str) -> Result<(String, Vec<String>)> {
         }
     }
 
-    Ok((title.into(), tags))
+    let id = match &doc["id"] {
+        Yaml::String(id) => Some(id.clone()),
+        Yaml::Integer(id) => Some(id.to_string()),
+        _ => None,
+    };
+
+    // obsidian writes "aliases", a single one or a list
+    let aliases = match (&doc["aliases"], &doc["alias"]) {
+        (Yaml::Array(aa), _) | (_, Yaml::Array(aa)) => aa
+            .iter()
+            .filter_map(|a| a.as_str().map(String::from))
+            .collect(),
+        (Yaml::String(a), _) | (_, Yaml::String(a)) => vec![a.clone()],
+        _ => vec![],
+    };
+
+    Ok(FrontMatter {
+        title: title.into(),
+        tags,
+        id,
+        aliases,
+    })
 }
 
 static YAML_DELIM: &str = "---";
 
-fn get_yaml_header(lines: Lines<BufReader<File>>) -> Result<String> {
+pub fn is_yaml_delim(line: &str) -> bool {
+    remove_whitespace(line) == YAML_DELIM
+}
+
+// nothing is read past the closing delimiter
+fn get_yaml_header<R: BufRead>(lines: Lines<R>) -> Result<String> {
     let mut header = Vec::new();
     let mut copy_yaml = false;
 
     for (i, line) in lines.enumerate() {
         let l = line?;
-        if remove_whitespace(l.as_str()) == YAML_DELIM {
-            if i == 0 {
-                copy_yaml = true;
-            } else {
-                copy_yaml = false;
+        if is_yaml_delim(&l) {
+            if i > 0 {
+                break;
             }
+            copy_yaml = true;
         } else if copy_yaml {
             header.extend(format!("{}\n", l).as_bytes().to_vec());
         } else {
@@ -166,7 +335,9 @@ fn remove_whitespace(s: &str) -> String {
 mod tests {
     use super::*;
     use std::fs::File;
-    use tempfile::tempdir_in;
+    use std::io::Cursor;
+    use std::time::Instant;
+    use tempfile::{tempdir, tempdir_in};
 
     #[test]
     fn yaml_to_meta_basic() -> std::io::Result<()> {
@@ -177,12 +348,31 @@ tags:
 ";
 
         assert_eq!(
-            yaml_to_meta(&yaml)?,
-            (
-                String::from("my cool title"),
-                vec!["rust".into(), "programming languages".into()]
-            )
+            yaml_to_meta(yaml)?,
+            FrontMatter {
+                title: String::from("my cool title"),
+                tags: vec!["rust".into(), "programming languages".into()],
+                ..FrontMatter::default()
+            }
+        );
+        assert_eq!(
+            FrontMatter {
+                title: "t".into(),
+                id: Some("ownership".into()),
+                aliases: vec!["own".into(), "borrow".into()],
+                ..FrontMatter::default()
+            },
+            yaml_to_meta("title: t\nid: ownership\naliases: [own, borrow]\n")?
+        );
+        assert_eq!(
+            vec!["own".to_string()],
+            yaml_to_meta("title: t\nalias: own\n")?.aliases
+        );
+        assert_eq!(
+            vec!["a".to_string()],
+            parse_front_matter("---\ntitle: t\ntags: [a]\n---\n# body\n")?.tags
         );
+        assert!(parse_front_matter("# no header\n").is_err());
         Ok(())
     }
 
@@ -203,4 +393,102 @@ rest
         assert_eq!(get_yaml_header(BufReader::new(file).lines())?, "salut\n");
         Ok(())
     }
+
+    #[test]
+    fn get_yaml_header_stops_at_delim() -> std::io::Result<()> {
+        // the body is never read
+        let mut content = b"---\ntitle: t\n---\n".to_vec();
+        content.extend(vec![0xff, 0xfe, b'\n']);
+
+        assert_eq!(get_yaml_header(Cursor::new(content).lines())?, "title: t\n");
+        Ok(())
+    }
+
+    #[test]
+    fn parser_pool() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let ok = dir.path().join("ok.md");
+        fs::write(&ok, "---\ntitle: t\ntags:\n- a\n---\n")?;
+
+        let pool = ParserPool::new(2, &Store::new());
+        task::block_on(async {
+            assert_eq!(
+                TaggedArticle::new(ok.clone(), "t", &["a".into()]),
+                pool.parse(&ok, false).await?.meta
+            );
+            assert!(pool
+                .parse(&dir.path().join("missing.md"), true)
+                .await
+                .is_err());
+            Ok(())
+        })
+    }
+
+    // cargo test --release bench_initial_scan -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_initial_scan() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let body = "lorem ipsum dolor sit amet\n".repeat(200);
+        let paths: Vec<PathBuf> = (0..20_000)
+            .map(|i| {
+                let p = dir.path().join(format!("note_{}.md", i));
+                let content = format!(
+                    "---\ntitle: note {}\ntags:\n- tag_{}\n- tag_{}\n---\n{}",
+                    i,
+                    i % 10,
+                    i % 7,
+                    body
+                );
+                fs::write(&p, content).map(|_| p)
+            })
+            .collect::<Result<_>>()?;
+
+        let start = Instant::now();
+        // every side parses the whole file, as the indexing does
+        let parsed = paths.iter().filter(|p| parse(p).is_ok()).count();
+        println!("single thread : {:?}", start.elapsed());
+        assert_eq!(paths.len(), parsed);
+
+        let start = Instant::now();
+        let handles: Vec<_> = paths
+            .iter()
+            .cloned()
+            .map(|p| task::spawn(async move { parse(&p).is_ok() }))
+            .collect();
+        let parsed = task::block_on(async {
+            let mut n = 0;
+            for h in handles {
+                n += h.await as usize;
+            }
+            n
+        });
+        println!("executor tasks : {:?}", start.elapsed());
+        assert_eq!(paths.len(), parsed);
+
+        let pool = ParserPool::sized_to_cpus(&Store::new());
+        let start = Instant::now();
+        let handles: Vec<_> = paths
+            .iter()
+            .cloned()
+            .map(|p| {
+                let pc = pool.clone();
+                task::spawn(async move { pc.parse(&p, false).await.is_ok() })
+            })
+            .collect();
+        let parsed = task::block_on(async {
+            let mut n = 0;
+            for h in handles {
+                n += h.await as usize;
+            }
+            n
+        });
+        println!(
+            "parser pool ({} workers) : {:?}",
+            pool.workers,
+            start.elapsed()
+        );
+        assert_eq!(paths.len(), parsed);
+        Ok(())
+    }
 }
@@ -0,0 +1,254 @@
+use crate::article_writer;
+use actix_web::http::header::{
+    HttpDate, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse};
+use async_std::io::{Read as AsyncRead, ReadExt as _};
+use async_std::stream::Stream;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::UNIX_EPOCH;
+
+// open ended ranges (bytes=123-) are answered by chunks, media players ask for the rest
+const MAX_CHUNK: u64 = 4 * 1024 * 1024;
+
+// the body is read and sent by pieces of this size
+const READ_SIZE: u64 = 64 * 1024;
+
+// the file with its type, validators (304 when the client copy is fresh) and ranges
+pub fn respond(
+    req: &HttpRequest,
+    p: &Path,
+    content_type: Option<&str>,
+) -> io::Result<HttpResponse> {
+    let meta = fs::metadata(p)?;
+    let len = meta.len();
+    let etag = article_writer::etag(p)?;
+    let modified = HttpDate::from(meta.modified()?);
+    let content_type = match content_type {
+        Some(ct) => ct.to_string(),
+        None => guess_type(p)?,
+    };
+
+    let mut builder = HttpResponse::Ok();
+    builder
+        .header(ETAG, etag.as_str())
+        .header(LAST_MODIFIED, modified.to_string())
+        .header(CACHE_CONTROL, "no-cache") // always revalidated, cheap with the validators
+        .header(ACCEPT_RANGES, "bytes");
+
+    if is_fresh(req, &etag, &meta) {
+        return Ok(builder.status(StatusCode::NOT_MODIFIED).finish());
+    }
+
+    let range = header(req, RANGE).filter(|_| {
+        // the range only applies to the version the client already has part of
+        header(req, IF_RANGE).is_none_or(|v| v == etag || v == modified.to_string())
+    });
+    let (start, end) = match range.map(|r| parse_range(r, len)) {
+        None | Some(Range::Ignored) => (0, len),
+        Some(Range::Unsatisfiable) => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(CONTENT_RANGE, format!("bytes */{}", len))
+                .finish());
+        }
+        Some(Range::Bytes(start, end)) => {
+            builder.status(StatusCode::PARTIAL_CONTENT).header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end - 1, len),
+            );
+            (start, end)
+        }
+    };
+
+    let body = Pieces::new(File::open(p)?, start, end - start)?;
+    Ok(builder.header(CONTENT_TYPE, content_type).streaming(body))
+}
+
+// the range of the file, piece by piece,
+// read on the async-std blocking pool, not on the server workers
+struct Pieces {
+    file: async_std::io::Take<async_std::fs::File>,
+    piece: Vec<u8>,
+}
+
+impl Pieces {
+    fn new(mut f: File, start: u64, len: u64) -> io::Result<Self> {
+        f.seek(SeekFrom::Start(start))?;
+        Ok(Pieces {
+            file: async_std::fs::File::from(f).take(len),
+            piece: vec![0; READ_SIZE as usize],
+        })
+    }
+}
+
+impl Stream for Pieces {
+    type Item = Result<Bytes, actix_web::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Pieces { file, piece } = &mut *self;
+        match Pin::new(file).poll_read(cx, piece) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(0)) => Poll::Ready(None),
+            Poll::Ready(Ok(n)) => Poll::Ready(Some(Ok(Bytes::copy_from_slice(&piece[..n])))),
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e.into()))),
+        }
+    }
+}
+
+fn header(req: &HttpRequest, name: actix_web::http::header::HeaderName) -> Option<&str> {
+    req.headers().get(name).and_then(|h| h.to_str().ok())
+}
+
+// If-None-Match wins over If-Modified-Since
+fn is_fresh(req: &HttpRequest, etag: &str, meta: &fs::Metadata) -> bool {
+    if let Some(tags) = header(req, IF_NONE_MATCH) {
+        return tags
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == etag || t == "*");
+    }
+
+    let since = header(req, IF_MODIFIED_SINCE).and_then(|d| d.parse::<HttpDate>().ok());
+    match (since, meta.modified()) {
+        (Some(since), Ok(modified)) => {
+            let secs = |t: std::time::SystemTime| {
+                t.duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0)
+            };
+            secs(modified) <= secs(since.into())
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Range {
+    Bytes(u64, u64), // end excluded
+    Unsatisfiable,
+    Ignored, // several ranges or garbage : the whole file is sent
+}
+
+fn parse_range(r: &str, len: u64) -> Range {
+    let spec = match r.trim().strip_prefix("bytes=") {
+        Some(s) if !s.contains(',') => s.trim(),
+        _ => return Range::Ignored,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(se) => se,
+        None => return Range::Ignored,
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // the last n bytes
+        (Err(_), Ok(n)) if start.is_empty() => (len.saturating_sub(n), len),
+        (Ok(s), Err(_)) if end.is_empty() => (s, len.min(s.saturating_add(MAX_CHUNK))),
+        (Ok(s), Ok(e)) if s <= e => (s, len.min(e.saturating_add(1))),
+        _ => return Range::Ignored,
+    };
+    if range.0 >= range.1 {
+        return Range::Unsatisfiable;
+    }
+    Range::Bytes(range.0, range.1)
+}
+
+// by extension, then by the first bytes
+fn guess_type(p: &Path) -> io::Result<String> {
+    if let Some(mime) = mime_guess::from_path(p).first() {
+        return Ok(mime.to_string());
+    }
+
+    let mut head = vec![];
+    File::open(p)?.take(16).read_to_end(&mut head)?;
+    Ok(article_writer::image_type(&head)
+        .unwrap_or("application/octet-stream")
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use async_std::stream::StreamExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn parse_range_basic() -> std::io::Result<()> {
+        assert_eq!(Range::Bytes(0, 10), parse_range("bytes=0-9", 100));
+        assert_eq!(Range::Bytes(90, 100), parse_range("bytes=90-200", 100));
+        assert_eq!(Range::Bytes(95, 100), parse_range("bytes=-5", 100));
+        assert_eq!(Range::Bytes(10, 100), parse_range("bytes=10-", 100));
+        assert_eq!(
+            Range::Bytes(0, MAX_CHUNK),
+            parse_range("bytes=0-", 10 * MAX_CHUNK)
+        );
+        assert_eq!(Range::Unsatisfiable, parse_range("bytes=100-", 100));
+        assert_eq!(Range::Unsatisfiable, parse_range("bytes=-0", 100));
+        assert_eq!(Range::Ignored, parse_range("bytes=0-1,5-6", 100));
+        assert_eq!(Range::Ignored, parse_range("bytes=9-1", 100));
+        assert_eq!(Range::Ignored, parse_range("lines=1-2", 100));
+        Ok(())
+    }
+
+    #[test]
+    fn conditional_and_ranges() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let p = dir.path().join("a.svg");
+        fs::write(&p, "<svg></svg>")?;
+        let etag = article_writer::etag(&p)?;
+
+        let resp = respond(&TestRequest::default().to_http_request(), &p, None)?;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("image/svg+xml", resp.headers().get(CONTENT_TYPE).unwrap());
+
+        let fresh = TestRequest::default()
+            .header(IF_NONE_MATCH, etag.as_str())
+            .to_http_request();
+        assert_eq!(
+            StatusCode::NOT_MODIFIED,
+            respond(&fresh, &p, None)?.status()
+        );
+
+        let part = TestRequest::default()
+            .header(RANGE, "bytes=1-3")
+            .to_http_request();
+        let resp = respond(&part, &p, None)?;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, resp.status());
+        assert_eq!("bytes 1-3/11", resp.headers().get(CONTENT_RANGE).unwrap());
+
+        // the client copy is outdated, the whole file is sent
+        let outdated = TestRequest::default()
+            .header(RANGE, "bytes=1-3")
+            .header(IF_RANGE, "\"old\"")
+            .to_http_request();
+        assert_eq!(StatusCode::OK, respond(&outdated, &p, None)?.status());
+        Ok(())
+    }
+
+    #[test]
+    fn range_by_pieces() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let p = dir.path().join("a.bin");
+        let data: Vec<u8> = (0..3 * READ_SIZE + 10).map(|i| i as u8).collect();
+        fs::write(&p, &data)?;
+
+        let pieces = Pieces::new(File::open(&p)?, 5, 2 * READ_SIZE + 1)?;
+        let pieces = pieces.map(|r| r.map_err(|e| io::Error::other(e.to_string())));
+        let pieces = async_std::task::block_on(pieces.collect::<io::Result<Vec<_>>>())?;
+        assert_eq!(3, pieces.len());
+        assert_eq!(1, pieces[2].len());
+        assert_eq!(
+            &data[5..5 + 2 * READ_SIZE as usize + 1],
+            &pieces.concat()[..]
+        );
+        Ok(())
+    }
+}
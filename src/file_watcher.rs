@@ -1,18 +1,30 @@
 use crate::domain::{FileEvent, FileOp};
+use crate::event_coalescer;
 use async_std::{sync::Sender, task};
 use log::info;
 use notify::{
-    watcher,
+    watcher, DebouncedEvent,
     DebouncedEvent::{Create, Remove, Rename, Write},
     RecursiveMode::Recursive,
     Watcher,
 };
-use std::{io, path::PathBuf, sync::mpsc::channel, time::Duration};
+use std::{
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::mpsc::{channel, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+// how often the sources of the pipeline check whether it was stopped (see uc::Index)
+pub const STOP_CHECK: Duration = Duration::from_millis(100);
 
+// until stopped : the watcher goes, then the senders of the events
 pub fn watch(
     root_path: &PathBuf,
     send_chan: &Sender<FileEvent>,
     debounce: u64,
+    stopped: &AtomicBool,
 ) -> Result<(), io::Error> {
     info!("watching for file changes in {:?}", root_path.clone());
 
@@ -29,62 +41,73 @@ pub fn watch(
 
     let _ = w.watch(root_path, Recursive);
 
-    for e in rx.iter() {
-        match e {
-            Create(p) => {
-                info!("adding new file {:?}", p);
-                let s = send_chan.clone();
-                task::spawn(async move {
-                    s.send(FileEvent {
-                        op: FileOp::Create,
-                        path: p,
-                        dst: None,
-                    })
-                    .await
-                });
-            }
-            Write(p) => {
-                info!("checking for updates in {:?}", p);
-                let s = send_chan.clone();
-                task::spawn(async move {
-                    s.send(FileEvent {
-                        op: FileOp::Write,
-                        path: p,
-                        dst: None,
-                    })
-                    .await;
-                });
-            }
-            Rename(src, dst) => {
-                info!("updating path from {:?} to {:?}", src, dst);
-                let s = send_chan.clone();
-                task::spawn(async move {
-                    s.send(FileEvent {
-                        op: FileOp::Move,
-                        path: src,
-                        dst: Some(dst),
-                    })
-                    .await;
-                });
-            }
-            Remove(p) => {
-                info!("removing file {:?}", p);
-                let s = send_chan.clone();
-                task::spawn(async move {
-                    s.send(FileEvent {
-                        op: FileOp::Remove,
-                        path: p,
-                        dst: None,
-                    })
-                    .await;
-                });
+    // the debouncer fires the events of a burst together : they are gathered
+    // until the watcher stays quiet for a debounce period, then coalesced
+    let quiet = Duration::from_millis(debounce);
+    while !stopped.load(Ordering::SeqCst) {
+        let first = match rx.recv_timeout(STOP_CHECK) {
+            Ok(e) => e,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let mut burst = vec![first];
+        let started = Instant::now();
+        while burst.len() < MAX_BURST && started.elapsed() < quiet * 10 {
+            match rx.recv_timeout(quiet) {
+                Ok(e) => burst.push(e),
+                Err(_) => break,
             }
-            _ => {}
+        }
+
+        let events = burst.into_iter().filter_map(to_file_event).collect();
+        for fe in event_coalescer::coalesce(events) {
+            // sent in order : the handler relies on it
+            task::block_on(send_chan.send(fe));
         }
     }
     Ok(())
 }
 
+const MAX_BURST: usize = 1000;
+
+fn to_file_event(e: DebouncedEvent) -> Option<FileEvent> {
+    match e {
+        Create(p) => {
+            info!("adding new file {:?}", p);
+            Some(FileEvent {
+                op: FileOp::Create,
+                path: p,
+                dst: None,
+            })
+        }
+        Write(p) => {
+            info!("checking for updates in {:?}", p);
+            Some(FileEvent {
+                op: FileOp::Write,
+                path: p,
+                dst: None,
+            })
+        }
+        Rename(src, dst) => {
+            info!("updating path from {:?} to {:?}", src, dst);
+            Some(FileEvent {
+                op: FileOp::Move,
+                path: src,
+                dst: Some(dst),
+            })
+        }
+        Remove(p) => {
+            info!("removing file {:?}", p);
+            Some(FileEvent {
+                op: FileOp::Remove,
+                path: p,
+                dst: None,
+            })
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,12 +124,13 @@ mod tests {
 
         let (tx, rx): (Sender<FileEvent>, Receiver<FileEvent>) = channel(1000);
 
-        let debounce: u64 = 1;
-        thread::spawn(move || watch(&dir.path().to_path_buf(), &tx.clone(), debounce));
+        let debounce: u64 = 10;
+        let stopped = AtomicBool::new(false);
+        thread::spawn(move || watch(&dir.path().to_path_buf(), &tx.clone(), debounce, &stopped));
         thread::sleep(std::time::Duration::from_millis(debounce + 10)); // looks fragile ?
 
         // Create
-        fs::File::create(&file_1.clone())?;
+        fs::File::create(file_1.clone())?;
         assert_eq!(
             Ok(FileEvent {
                 op: FileOp::Create,
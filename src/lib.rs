@@ -0,0 +1,37 @@
+// the knowledge base as a library : the parser, the store, the tag queries and the indexer,
+// to be embedded in other services ; the binary is a thin wrapper around it
+mod article_writer;
+mod disk_backend;
+mod domain;
+mod event_coalescer;
+mod file_handler;
+mod file_response;
+mod file_watcher;
+mod link_graph;
+mod metadata_handler;
+mod multipart;
+mod renderer;
+mod similarity;
+mod site;
+mod storage;
+mod tree_traverser;
+
+// the server and the subcommands of the binary, not part of the library
+#[doc(hidden)]
+pub mod api;
+#[doc(hidden)]
+pub mod auth;
+#[doc(hidden)]
+pub mod cli;
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod tls;
+#[doc(hidden)]
+pub mod uc;
+
+pub use disk_backend::DiskBackend;
+pub use domain::{ArtRef, Exp, Op, Root, Rules, SymlinkPolicy, TaggedArticle};
+pub use file_handler::{get_metadata, parse_front_matter, FrontMatter};
+pub use storage::{Backend, MemoryBackend, Parsed, Store};
+pub use uc::{index, scan, scan_into, search_by_tag, Index, Query};
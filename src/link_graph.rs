@@ -0,0 +1,55 @@
+// the links and anchors of the articles, parsed with them and kept up to date (see
+// MetadataEvent) : the link graph is resolved from them without reading the files again
+use crate::renderer;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Outline {
+    pub links: Vec<String>,     // the relative and wiki links, as written
+    pub slugs: HashSet<String>, // the anchors of the headings
+}
+
+impl Outline {
+    pub fn parse(content: &str) -> Outline {
+        Outline {
+            links: renderer::links(content),
+            slugs: renderer::outline(content)
+                .into_iter()
+                .map(|h| h.slug)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    outlines: DashMap<PathBuf, Outline>,
+}
+
+impl LinkGraph {
+    pub fn set(&self, p: &Path, outline: Outline) {
+        self.outlines.insert(p.to_path_buf(), outline);
+    }
+
+    pub fn remove(&self, p: &Path) {
+        self.outlines.remove(p);
+    }
+
+    // the links are relative : they are resolved from the new folder
+    pub fn rename(&self, s: &Path, d: &Path) {
+        if let Some((_, outline)) = self.outlines.remove(s) {
+            self.outlines.insert(d.to_path_buf(), outline);
+        }
+    }
+
+    pub fn get(&self, p: &Path) -> Option<Outline> {
+        self.outlines.get(p).map(|o| o.value().clone())
+    }
+
+    pub fn has_anchor(&self, p: &Path, slug: &str) -> bool {
+        self.outlines.get(p).is_some_and(|o| o.slugs.contains(slug))
+    }
+}
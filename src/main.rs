@@ -1,54 +1,85 @@
 use clap::{App, Arg, ArgMatches};
-use lazy_static::lazy_static;
-use rand::Rng;
 use simple_logger::SimpleLogger;
-use std::{env, thread};
-use std::{io, path::PathBuf};
+use std::io;
+use std::path::Path;
+use std::thread;
 
-use storage::Store;
-
-mod api;
-mod domain;
-mod file_handler;
-mod file_watcher;
-mod metadata_handler;
-mod storage;
-mod tree_traverser;
-mod uc;
+use knowledge::config::{self, Config, Listen};
+use knowledge::{api, auth, cli, uc, Store};
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
-    welcome();
-
-    init_logger();
     let mm = cli_setup();
+    let (cfg, sources) = config::load(&mm)?;
+    init_logger(cfg.log_level()?);
 
-    lazy_static! {
-        static ref STORE: Store = Store::new();
+    if mm.subcommand_matches("hash-password").is_some() {
+        return hash_password();
+    }
+    if mm.subcommand_matches("hash-token").is_some() {
+        return hash_token();
+    }
+    if let Some(sub) = mm.subcommand_matches("config") {
+        return show_config(&cfg, &sources, sub);
+    }
+    if let Some(sub) = mm.subcommand_matches("rename-tag") {
+        return rename_tag(&cfg, sub);
+    }
+    if let Some(sub) = mm.subcommand_matches("export") {
+        return export(&cfg, sub);
+    }
+    match mm.subcommand() {
+        Some((name @ ("tags" | "list" | "search" | "show"), sub)) => return query(&cfg, name, sub),
+        Some((name, _)) => return Err(unknown_subcommand(name)),
+        None => {}
     }
 
-    {
-        let f = get_folder(&mm)?;
-        thread::spawn(move || uc::build_graph_start_watcher(&f, &STORE));
+    let print_url = mm.is_present("print_url");
+    if !print_url {
+        welcome();
+    }
+
+    let mut vaults = vec![];
+    for (prefix, c) in cfg.vaults()? {
+        let index = knowledge::index(c.store()?, &c.roots()?, c.symlinks, c.debounce)?;
+        vaults.push(api::Vault {
+            prefix,
+            store: index.store(),
+        });
     }
 
     {
-        let bind_addr = format!("localhost:{}", get_listen_port(&mm));
-        let dev_mode = in_dev_mode(&mm);
-        if !dev_mode {
-            let url = format!("http://{}", bind_addr.clone());
+        let listen = cfg.listen()?;
+        let (server, listen) = api::server(
+            &listen,
+            vaults,
+            cfg.dev,
+            cfg.auth(),
+            &cfg.attachments,
+            &cfg.cors_origins,
+            cfg.tls()?,
+        )?;
+
+        // once bound (the port is known), a script waiting for the url can use it right away
+        let url = listen.url(cfg.https());
+        if print_url {
+            let printed = match &listen {
+                Listen::Tcp(..) => serde_json::json!({ "url": url }),
+                Listen::Unix(p) => serde_json::json!({ "socket": p }),
+            };
+            println!("{}", printed);
+        } else {
+            println!("listening on : {}", url.as_deref().unwrap_or(&cfg.bind));
+        }
+        if let Some(url) = url.filter(|_| cfg.open_browser && !cfg.dev) {
             thread::spawn(move || webbrowser::open(&url));
         }
-
-        api::server(&bind_addr, &STORE, dev_mode)?.await
+        server.await
     }
 }
 
-fn init_logger() {
-    SimpleLogger::new()
-        .with_level(log::LevelFilter::Error)
-        .init()
-        .unwrap();
+fn init_logger(level: log::LevelFilter) {
+    SimpleLogger::new().with_level(level).init().unwrap();
 }
 
 fn welcome() {
@@ -71,7 +102,88 @@ fn cli_setup() -> ArgMatches {
             Arg::new("folder")
                 .short('f')
                 .long("folder")
-                .about("the root folder")
+                .about("a root folder, optionally named (name=path), can be repeated")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("symlinks")
+                .short('s')
+                .long("symlinks")
+                .about("how to handle symlinks found in the root folder")
+                .possible_values(&["ignore", "follow", "follow-once"])
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .about("the address listened on : host, host:port or unix:/path/to.sock")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tls_cert")
+                .long("tls-cert")
+                .about("serves https with this certificate (pem), along with --tls-key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tls_key")
+                .long("tls-key")
+                .about("the private key (pem) of the certificate")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("self_signed")
+                .long("self-signed")
+                .about("serves https with a certificate made on the first run, then kept")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("http_port")
+                .long("http-port")
+                .about("with https, a plain http port redirecting to it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("no_browser")
+                .long("no-browser")
+                .about("doesn't open the browser at startup")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("print_url")
+                .long("print-url")
+                .about("only prints where the server listens, as a json line, once it's bound")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("debounce")
+                .long("debounce")
+                .about("how long file changes are gathered before being applied (ms, default 200)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("write_token")
+                .long("write-token")
+                .about("enables the write api, for requests bearing this token")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("index_dir")
+                .long("index-dir")
+                .about(
+                    "keeps the index on disk in this folder, for large vaults and quick restarts",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("attachments")
+                .long("attachments")
+                .about(
+                    "where the uploaded images go, relative to their article (default attachments)",
+                )
                 .takes_value(true),
         )
         .arg(
@@ -81,27 +193,197 @@ fn cli_setup() -> ArgMatches {
                 .about("run in dev mode")
                 .takes_value(false),
         )
+        .subcommand(
+            App::new("hash-password")
+                .about("hashes the password read on stdin, for the users of the [auth] settings"),
+        )
+        .subcommand(
+            App::new("hash-token")
+                .about("hashes the token read on stdin, for the tokens of the [auth] settings"),
+        )
+        .subcommand(
+            App::new("config")
+                .about("the settings, from knowledge.toml, the env (KNOWLEDGE_*) and the flags")
+                .subcommand(App::new("show").about("prints the effective settings")),
+        )
+        .subcommand(
+            App::new("rename-tag")
+                .about("renames tags in the header of every article, merging them if several")
+                .arg(
+                    Arg::new("tags")
+                        .about("the tags to rename, then the new tag")
+                        .required(true)
+                        .multiple(true)
+                        .min_values(2),
+                )
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .about("only list the files that would change")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            App::new("export")
+                .about("writes a static website of the articles")
+                .arg(Arg::new("out").about("the output folder").required(true))
+                .arg(
+                    Arg::new("tags")
+                        .long("tags")
+                        .about(
+                            "the articles to export, e.g. 'public and (rust or go)' (default all)",
+                        )
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("tags")
+                .about("lists the tags and their number of articles")
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("list")
+                .about("lists the articles bearing all the given tags")
+                .arg(
+                    Arg::new("tag")
+                        .long("tag")
+                        .about("a tag, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .about("only the articles of this root folder")
+                        .takes_value(true),
+                )
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("search")
+                .about("lists the articles matching a tag query")
+                .arg(
+                    Arg::new("query")
+                        .about("e.g. 'rust and (web or \"programming languages\")'")
+                        .required(true),
+                )
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("show")
+                .about("prints an article, with its metadata in json")
+                .arg(
+                    Arg::new("path")
+                        .about("the path or the id of the article")
+                        .required(true),
+                )
+                .arg(json_arg()),
+        )
         .get_matches()
 }
 
-fn get_folder(mm: &ArgMatches) -> io::Result<PathBuf> {
-    let f: String = mm.value_of_t("folder").unwrap_or_else(|_| String::new());
-    if f.is_empty() {
-        return env::current_dir();
+fn json_arg() -> Arg<'static> {
+    Arg::new("json")
+        .long("json")
+        .about("prints json instead of a table")
+        .takes_value(false)
+}
+
+fn hash_password() -> io::Result<()> {
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty password",
+        ));
     }
+    let hash = auth::hash_password(password).map_err(io::Error::other)?;
+    println!("{}", hash);
+    Ok(())
+}
 
-    Ok(PathBuf::from(f))
+fn hash_token() -> io::Result<()> {
+    let mut token = String::new();
+    io::stdin().read_line(&mut token)?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty token"));
+    }
+    println!("{}", auth::hash_token(token));
+    Ok(())
 }
 
-fn get_listen_port(mm: &ArgMatches) -> u16 {
-    if in_dev_mode(mm) {
-        return 8080;
+fn show_config(cfg: &Config, sources: &[String], sub: &ArgMatches) -> io::Result<()> {
+    if sub.subcommand_matches("show").is_some() {
+        println!("# {}", sources.join(", "));
+        print!("{}", cfg.show()?);
     }
+    Ok(())
+}
+
+fn rename_tag(cfg: &Config, sub: &ArgMatches) -> io::Result<()> {
+    let mut tags: Vec<String> = sub.values_of_t("tags").unwrap_or_default();
+    let to = tags.pop().unwrap_or_default();
+    let dry_run = sub.is_present("dry_run");
+
+    let store = scan(cfg)?;
+    for p in uc::rename_tags(&tags, &to, dry_run, &store)? {
+        println!("{}", p.display());
+    }
+    Ok(())
+}
+
+fn export(cfg: &Config, sub: &ArgMatches) -> io::Result<()> {
+    let query = match sub.value_of("tags") {
+        Some(q) => Some(
+            q.parse::<uc::Query>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        ),
+        None => None,
+    };
+    let out = sub.value_of("out").unwrap_or_default();
+
+    let store = scan(cfg)?;
+    let summary = uc::export_site(query.as_ref(), Path::new(out), &store)?;
+    println!(
+        "{} articles and {} assets exported to {}",
+        summary.articles, summary.assets, out
+    );
+    Ok(())
+}
+
+// the subcommands that only read the articles
+fn query(cfg: &Config, name: &str, sub: &ArgMatches) -> io::Result<()> {
+    let store = scan(cfg)?;
+    let json = sub.is_present("json");
+    let out = match name {
+        "tags" => cli::tags(&store, json),
+        "list" => cli::list(
+            &sub.values_of_t("tag").unwrap_or_default(),
+            &sub.value_of("root").map(String::from),
+            &store,
+            json,
+        ),
+        "search" => cli::search(sub.value_of("query").unwrap_or_default(), &store, json),
+        "show" => cli::show(sub.value_of("path").unwrap_or_default(), &store, json),
+        _ => Err(unknown_subcommand(name)),
+    }?;
+    print!("{}", out);
+    Ok(())
+}
 
-    mm.value_of_t("port")
-        .unwrap_or_else(|_| rand::thread_rng().gen_range(3000, 10000))
+fn unknown_subcommand(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unknown subcommand: {}", name),
+    )
 }
 
-fn in_dev_mode(mm: &ArgMatches) -> bool {
-    mm.is_present("dev_mode")
+// with the index kept on disk when configured
+fn scan(cfg: &Config) -> io::Result<Store> {
+    let store = cfg.store()?;
+    uc::scan_into(&store, &cfg.roots()?, cfg.symlinks);
+    Ok(store)
 }
@@ -1,21 +1,53 @@
-use crate::domain::MetadataEvent;
-use crate::storage;
+use crate::domain::TaggedArticle;
+use crate::storage::{self, Parsed};
 use async_std::{sync::Receiver, task};
+use std::path::PathBuf;
 
+// what the file handler found, for the store
+#[derive(Debug)]
+pub enum MetadataEvent {
+    Create(Parsed),
+    Move(PathBuf, PathBuf),
+    Remove(PathBuf),
+    Changed(Parsed),
+}
+
+// until the file handler is gone
 pub fn watch(rch: &Receiver<MetadataEvent>, store: &storage::Store) {
     task::block_on(async {
-        loop {
-            match rch.recv().await {
-                Ok(me) => match me {
-                    MetadataEvent::Create(m) => store.insert(&m),
-                    MetadataEvent::Remove(p) => store.remove(&p),
-                    MetadataEvent::Move(src, dst) => store.update_path(&src, &dst),
-                    MetadataEvent::Changed(m) => store.update_meta(&m),
-                },
-                Err(_) => {
-                    continue;
+        while let Ok(me) = rch.recv().await {
+            match me {
+                // the watcher reports files behind every symlink, the ones
+                // the traversal didn't follow are outside of the roots
+                MetadataEvent::Create(parsed) | MetadataEvent::Changed(parsed) => {
+                    index(parsed, store)
                 }
-            };
+                MetadataEvent::Move(src, dst) if store.is_allowed(&dst) => {
+                    store.update_path(&src, &dst)
+                }
+                MetadataEvent::Move(src, _) => store.remove(&src),
+                MetadataEvent::Remove(p) => store.remove(&p),
+            }
         }
     });
 }
+
+// an article read from its file, placed in the roots
+pub fn index(parsed: Parsed, store: &storage::Store) {
+    if let Some(m) = with_root(parsed.meta.clone(), store) {
+        store.update_meta(&m);
+        store.index_content(&m.art.path, parsed);
+    }
+}
+
+// sets where the article is in the roots, its default id and its normalized tags
+pub fn with_root(mut m: TaggedArticle, store: &storage::Store) -> Option<TaggedArticle> {
+    let (root, rel_path) = store.locate(&m.art.path)?;
+    m.tags = store.normalize_tags(&m.tags);
+    if m.art.id.is_empty() {
+        m.art.id = store.default_id(&root, &rel_path);
+    }
+    m.art.root = root;
+    m.art.rel_path = rel_path;
+    Some(m)
+}
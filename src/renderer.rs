@@ -0,0 +1,503 @@
+use crate::domain::Heading;
+use crate::file_handler;
+use lazy_static::lazy_static;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+
+lazy_static! {
+    // loading the definitions takes a while, it's done once
+    static ref SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME: Theme = ThemeSet::load_defaults().themes["InspiredGitHub"].clone();
+}
+
+// where a link leads, as written
+pub enum Dest<'a> {
+    Path(&'a str), // relative, decoded
+    Wiki(&'a str), // [[target]] : a title, a file name or an alias
+}
+
+// commonmark + gfm to html, without the front matter ;
+// `link` gives the url of the relative links and images (None keeps them as is,
+// unresolved wiki links lead nowhere)
+pub fn render(content: &str, link: &dyn Fn(Dest) -> Option<String>) -> String {
+    let mut out_events = vec![];
+    let mut code: Option<(String, String)> = None; // language, code of the current block
+    let mut heading: Option<Vec<Event>> = None; // content of the current heading
+    let mut slugs = Slugs::default();
+
+    for e in events(content) {
+        match e {
+            Event::Start(Tag::Heading(..)) => heading = Some(vec![]),
+            Event::End(Tag::Heading(level, ..)) => {
+                let inner = heading.take().unwrap_or_default();
+                let slug = slugs.slug(&text_of(&inner));
+                out_events.push(Event::Html(format!("<{} id=\"{}\">", level, slug).into()));
+                out_events.extend(inner);
+                out_events.push(Event::Html(format!("</{}>\n", level).into()));
+            }
+            e if heading.is_some() => {
+                if let Some(h) = heading.as_mut() {
+                    h.push(rewrite_event(e, link));
+                }
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").into()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::Text(t) if code.is_some() => {
+                if let Some((_, c)) = code.as_mut() {
+                    c.push_str(&t);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((lang, c)) = code.take() {
+                    out_events.push(Event::Html(highlight(&lang, &c).into()));
+                }
+            }
+            e => out_events.push(rewrite_event(e, link)),
+        }
+    }
+
+    let mut out = String::new();
+    html::push_html(&mut out, out_events.into_iter());
+    out
+}
+
+// the headings, with the anchors given by `render`
+pub fn outline(content: &str) -> Vec<Heading> {
+    let mut headings = vec![];
+    let mut current: Option<Vec<Event>> = None;
+    let mut slugs = Slugs::default();
+
+    for e in events(content) {
+        match e {
+            Event::Start(Tag::Heading(..)) => current = Some(vec![]),
+            Event::End(Tag::Heading(level, ..)) => {
+                let text = text_of(&current.take().unwrap_or_default());
+                headings.push(Heading {
+                    level: level as u32,
+                    slug: slugs.slug(&text),
+                    text,
+                });
+            }
+            e => {
+                if let Some(c) = current.as_mut() {
+                    c.push(e);
+                }
+            }
+        }
+    }
+    headings
+}
+
+// the relative links, anchors and wiki links ([[target#heading]], without the label)
+pub fn links(content: &str) -> Vec<String> {
+    events(content)
+        .into_iter()
+        .filter_map(|e| match e {
+            Event::Start(Tag::Link(_, dest, _))
+                if is_relative(&dest) || dest.starts_with('#') || wiki_dest(&dest).is_some() =>
+            {
+                Some(dest.to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// the words of the body, without the markup nor the link destinations
+pub fn plain_text(content: &str) -> String {
+    events(content)
+        .iter()
+        .filter_map(|e| match e {
+            Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// "../a%20b.md#setup" -> ("../a b.md", Some("setup"))
+pub fn split_dest(dest: &str) -> (String, Option<String>) {
+    let (path, rest) = dest.split_at(dest.find(['#', '?']).unwrap_or(dest.len()));
+    let anchor = rest
+        .strip_prefix('#')
+        .filter(|a| !a.is_empty())
+        .map(percent_decode);
+    (percent_decode(path), anchor)
+}
+
+// "[[My note#Set up]]" -> ("My note", Some("set-up")), None when not a wiki link
+pub fn wiki_dest(dest: &str) -> Option<(String, Option<String>)> {
+    let inner = dest.strip_prefix("[[")?.strip_suffix("]]")?;
+    let (target, heading) = inner.split_at(inner.find('#').unwrap_or(inner.len()));
+    let anchor = heading
+        .strip_prefix('#')
+        .filter(|h| !h.trim().is_empty())
+        .map(|h| Slugs::default().slug(h));
+    Some((target.trim().to_string(), anchor))
+}
+
+// "Folder/My Note.md " -> "folder/my note", as compared with the articles' names
+pub fn wiki_target(target: &str) -> String {
+    let target = target.trim().to_lowercase();
+    match target.strip_suffix(".md") {
+        Some(t) => t.to_string(),
+        None => target,
+    }
+}
+
+// the events of the body, [[target|label]] becoming links
+fn events(content: &str) -> Vec<Event<'_>> {
+    let mut out = vec![];
+    let mut text = String::new(); // the brackets come as separate texts
+    let mut verbatim = 0; // inside code blocks, links or images
+
+    for e in Parser::new_ext(body(content), options()) {
+        if let Event::Text(t) = &e {
+            if verbatim == 0 {
+                text.push_str(t);
+                continue;
+            }
+        }
+        push_text(&std::mem::take(&mut text), &mut out);
+        match &e {
+            Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::Link(..))
+            | Event::Start(Tag::Image(..)) => verbatim += 1,
+            Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::Link(..))
+            | Event::End(Tag::Image(..)) => verbatim -= 1,
+            _ => {}
+        }
+        out.push(e);
+    }
+    push_text(&text, &mut out);
+    out
+}
+
+fn push_text(text: &str, out: &mut Vec<Event>) {
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let inner = &rest[start + 2..];
+        let end = match inner.find("]]") {
+            Some(end) if end > 0 && !inner[..end].contains('[') => end,
+            _ => {
+                out.push(Event::Text(rest[..start + 1].to_string().into()));
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+        if start > 0 {
+            out.push(Event::Text(rest[..start].to_string().into()));
+        }
+
+        let (target, label) = match inner[..end].split_once('|') {
+            Some((t, l)) => (t, l),
+            None => (&inner[..end], &inner[..end]),
+        };
+        let tag = Tag::Link(
+            LinkType::Inline,
+            format!("[[{}]]", target.trim()).into(),
+            "".into(),
+        );
+        out.push(Event::Start(tag.clone()));
+        out.push(Event::Text(label.trim().to_string().into()));
+        out.push(Event::End(tag));
+        rest = &inner[end + 2..];
+    }
+    if !rest.is_empty() {
+        out.push(Event::Text(rest.to_string().into()));
+    }
+}
+
+fn options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options
+}
+
+fn text_of(events: &[Event]) -> String {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+            _ => None,
+        })
+        .collect()
+}
+
+// github like : "Set up (v2)" -> "set-up-v2", then "set-up-v2-1" when repeated
+#[derive(Default)]
+struct Slugs {
+    seen: HashMap<String, usize>,
+}
+
+impl Slugs {
+    fn slug(&mut self, text: &str) -> String {
+        let base: String = text
+            .trim()
+            .to_lowercase()
+            .chars()
+            .filter_map(|c| match c {
+                c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+                c if c.is_whitespace() => Some('-'),
+                _ => None,
+            })
+            .collect();
+
+        let n = self.seen.entry(base.clone()).or_insert(0);
+        *n += 1;
+        match *n {
+            1 => base,
+            n => format!("{}-{}", base, n - 1),
+        }
+    }
+}
+
+fn rewrite_event<'a>(e: Event<'a>, link: &dyn Fn(Dest) -> Option<String>) -> Event<'a> {
+    match e {
+        Event::Start(Tag::Link(kind, dest, title)) => {
+            Event::Start(Tag::Link(kind, rewrite(dest, link), title))
+        }
+        Event::Start(Tag::Image(kind, dest, title)) => {
+            Event::Start(Tag::Image(kind, rewrite(dest, link), title))
+        }
+        e => e,
+    }
+}
+
+// what follows the header, if any
+fn body(content: &str) -> &str {
+    let mut lines = content.split_inclusive('\n');
+    match lines.next() {
+        Some(first) if file_handler::is_yaml_delim(first) => {}
+        _ => return content,
+    }
+
+    let mut offset = content.split_inclusive('\n').next().map_or(0, str::len);
+    for l in lines {
+        offset += l.len();
+        if file_handler::is_yaml_delim(l) {
+            return &content[offset..];
+        }
+    }
+    content // never closed, it wasn't a header
+}
+
+fn highlight(lang: &str, code: &str) -> String {
+    match SYNTAXES.find_syntax_by_token(lang) {
+        Some(syntax) if !lang.is_empty() => {
+            highlighted_html_for_string(code, &SYNTAXES, syntax, &THEME)
+                .unwrap_or_else(|_| plain_code(code))
+        }
+        _ => plain_code(code),
+    }
+}
+
+fn plain_code(code: &str) -> String {
+    let mut escaped = String::new();
+    let _ = pulldown_cmark::escape::escape_html(&mut escaped, code);
+    format!("<pre><code>{}</code></pre>\n", escaped)
+}
+
+fn rewrite<'a>(dest: CowStr<'a>, link: &dyn Fn(Dest) -> Option<String>) -> CowStr<'a> {
+    if let Some((target, anchor)) = wiki_dest(&dest) {
+        let anchor = anchor.map(|a| format!("#{}", a)).unwrap_or_default();
+        return match link(Dest::Wiki(&target)) {
+            _ if target.is_empty() => anchor.into(), // a heading of the article itself
+            Some(url) => format!("{}{}", url, anchor).into(),
+            None => "#".into(),
+        };
+    }
+    if !is_relative(&dest) {
+        return dest;
+    }
+    // the fragment or query stays on the new url
+    let (path, suffix) = dest.split_at(dest.find(['#', '?']).unwrap_or(dest.len()));
+    match link(Dest::Path(&percent_decode(path))) {
+        Some(url) => format!("{}{}", url, suffix).into(),
+        None => dest,
+    }
+}
+
+// neither an url, an absolute path nor an anchor
+fn is_relative(dest: &str) -> bool {
+    let before_slash = dest.split('/').next().unwrap_or("");
+    !(dest.is_empty()
+        || dest.starts_with('/')
+        || dest.starts_with('#')
+        || dest.starts_with('?')
+        || before_slash.contains(':'))
+}
+
+// `dest` from the folder `dir`, None when it goes above the root
+pub fn join_relative(dir: &Path, dest: &str) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for c in dir.join(dest).components() {
+        match c {
+            Component::Normal(n) => out.push(n),
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+// the slashes are kept, ids are written as paths
+pub fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_basic() -> std::io::Result<()> {
+        let content = "---\ntitle: t\n---\n\
+# Title\n\n\
+~~old~~ [other](../b%20c.md#part) [web](https://x.org) ![img](img/a.png)\n\n\
+- [x] done\n\n\
+| a |\n|---|\n| 1 |\n\n\
+note[^1]\n\n[^1]: foot\n\n\
+```rust\nfn main() {}\n```\n";
+        let link = |dest: Dest| match dest {
+            Dest::Path(p) => Some(format!("/api/{}", percent_encode(p))),
+            Dest::Wiki(_) => None,
+        };
+        let html = render(content, &link);
+
+        assert!(!html.contains("title: t"));
+        assert!(html.contains("<h1 id=\"title\">Title</h1>"));
+        assert!(html.contains("<del>old</del>"));
+        assert!(html.contains("href=\"/api/../b%20c.md#part\""));
+        assert!(html.contains("href=\"https://x.org\""));
+        assert!(html.contains("src=\"/api/img/a.png\""));
+        assert!(html.contains("type=\"checkbox\""));
+        assert!(html.contains("<table>"));
+        assert!(html.contains("footnote-definition"));
+        // highlighted
+        assert!(html.contains("<pre style="));
+        assert!(!html.contains("<code class=\"language-rust\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn outline_basic() -> std::io::Result<()> {
+        let content =
+            "---\ntitle: t\n---\n# Set *up* (v2)\n\n## `cargo` run\n\ntext\n\n## Set up v2\n";
+        let h = |level, text: &str, slug: &str| Heading {
+            level,
+            text: text.into(),
+            slug: slug.into(),
+        };
+        assert_eq!(
+            vec![
+                h(1, "Set up (v2)", "set-up-v2"),
+                h(2, "cargo run", "cargo-run"),
+                h(2, "Set up v2", "set-up-v2-1"),
+            ],
+            outline(content)
+        );
+
+        // same anchors in the html
+        let html = render(content, &|_| None);
+        assert!(html.contains("<h1 id=\"set-up-v2\">Set <em>up</em> (v2)</h1>"));
+        assert!(html.contains("<h2 id=\"set-up-v2-1\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn wiki_links() -> std::io::Result<()> {
+        let content = "# Intro\n\nsee [[My note#Set up|the setup]], [[other]] and [[#Intro]]\n\n\
+`[[code]]` [[ [x](y.md) ]] [[]]\n\n```\n[[block]]\n```\n";
+        assert_eq!(
+            vec!["[[My note#Set up]]", "[[other]]", "[[#Intro]]", "y.md"],
+            links(content)
+        );
+        assert_eq!(
+            Some(("My note".to_string(), Some("set-up".to_string()))),
+            wiki_dest("[[My note#Set up]]")
+        );
+        assert_eq!(None, wiki_dest("note.md"));
+
+        let link = |dest: Dest| match dest {
+            Dest::Wiki("My note") => Some("/api/articles/note".to_string()),
+            _ => None,
+        };
+        let html = render(content, &link);
+        assert!(html.contains("<a href=\"/api/articles/note#set-up\">the setup</a>"));
+        assert!(html.contains("<a href=\"#\">other</a>"));
+        assert!(html.contains("<a href=\"#intro\">#Intro</a>"));
+        assert!(html.contains("<code>[[code]]</code>"));
+        assert!(html.contains("[[]]"));
+        assert!(html.contains("[[block]]"));
+        Ok(())
+    }
+
+    #[test]
+    fn relative_paths() -> std::io::Result<()> {
+        let dir = Path::new("a/b");
+        assert_eq!(Some(PathBuf::from("a/c.md")), join_relative(dir, "../c.md"));
+        assert_eq!(
+            Some(PathBuf::from("a/b/c.md")),
+            join_relative(dir, "./c.md")
+        );
+        assert_eq!(None, join_relative(dir, "../../../c.md"));
+
+        assert!(is_relative("img/a.png"));
+        assert!(!is_relative("mailto:a@b.c"));
+        assert!(!is_relative("/abs"));
+        assert_eq!("my note.md", percent_decode("my%20note.md"));
+        assert_eq!("my%20n%C3%B4te/a.md", percent_encode("my nôte/a.md"));
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+// what the articles say and where they lead, for the related articles : the words are
+// weighted by tf-idf and compared by cosine, computed offline and kept up to date with
+// the articles (see MetadataEvent)
+use crate::domain::ArtRef;
+use crate::renderer;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// too common to tell the articles apart
+static STOP_WORDS: &[&str] = &[
+    "about", "all", "also", "and", "any", "are", "been", "but", "can", "each", "for", "from",
+    "has", "have", "how", "into", "its", "may", "more", "not", "one", "only", "other", "our",
+    "out", "some", "such", "than", "that", "the", "their", "them", "then", "there", "these",
+    "they", "this", "those", "very", "was", "were", "what", "when", "which", "who", "why", "will",
+    "with", "would", "you", "your",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Doc {
+    terms: HashMap<String, usize>, // occurrences of each word
+    links: HashSet<LinkKey>,
+    paths: Vec<String>, // the relative links as written, resolved again when the article moves
+}
+
+// where a link leads, before the articles are known
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LinkKey {
+    Path(PathBuf), // resolved from the folder of the article
+    Wiki(String),  // see renderer::wiki_target
+}
+
+impl LinkKey {
+    pub fn leads_to(&self, art: &ArtRef) -> bool {
+        match self {
+            LinkKey::Path(p) => *p == art.path,
+            LinkKey::Wiki(target) => art.is_named(target),
+        }
+    }
+}
+
+impl Doc {
+    // p : the file of the article, its relative links start from its folder
+    pub fn parse(p: &Path, content: &str) -> Doc {
+        let mut terms = HashMap::new();
+        for word in renderer::plain_text(content)
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| w.chars().count() > 2 && !w.chars().all(|c| c.is_numeric()))
+            .filter(|w| !STOP_WORDS.contains(w))
+        {
+            *terms.entry(word.to_string()).or_insert(0) += 1;
+        }
+
+        let mut links = HashSet::new();
+        let mut paths = vec![];
+        for dest in renderer::links(content) {
+            match renderer::wiki_dest(&dest) {
+                Some((target, _)) if !target.is_empty() => {
+                    links.insert(LinkKey::Wiki(renderer::wiki_target(&target)));
+                }
+                Some(_) => {}
+                None => {
+                    let (path, _) = renderer::split_dest(&dest);
+                    // or an anchor of the article itself
+                    if !path.is_empty() {
+                        paths.push(path.to_string());
+                    }
+                }
+            }
+        }
+        let mut doc = Doc {
+            terms,
+            links,
+            paths,
+        };
+        doc.resolve(p);
+        doc
+    }
+
+    // the relative links from the folder of p
+    fn resolve(&mut self, p: &Path) {
+        let dir = p.parent().unwrap_or_else(|| Path::new(""));
+        self.links.retain(|l| matches!(l, LinkKey::Wiki(_)));
+        for path in &self.paths {
+            let joined = dir.join(path);
+            let resolved = fs::canonicalize(&joined).unwrap_or(joined);
+            self.links.insert(LinkKey::Path(resolved));
+        }
+    }
+}
+
+// the tf-idf weights of the words of an article, divided by their norm
+type Vector = Arc<HashMap<String, f64>>;
+
+#[derive(Debug, Default)]
+pub struct Similarity {
+    docs: DashMap<PathBuf, Doc>,
+    df: DashMap<String, usize>, // the number of articles of each word
+    version: AtomicUsize,       // of the df and of the number of articles
+    vectors: DashMap<PathBuf, (usize, Vector)>, // computed when needed, by version
+    linking: DashMap<LinkKey, HashSet<PathBuf>>, // the articles with each link
+}
+
+impl Similarity {
+    // replaces the doc of p
+    pub fn set(&self, p: &Path, doc: Doc) {
+        let same_words = self.docs.get(p).is_some_and(|old| old.terms == doc.terms);
+        if same_words {
+            // eg: a link changed, the weights stay
+            if let Some(old) = self.docs.insert(p.to_path_buf(), doc.clone()) {
+                self.unlink(p, &old.links);
+            }
+            self.link(p, &doc.links);
+            return;
+        }
+        self.remove(p);
+        for t in doc.terms.keys() {
+            *self.df.entry(t.clone()).or_insert(0) += 1;
+        }
+        self.link(p, &doc.links);
+        self.docs.insert(p.to_path_buf(), doc);
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn remove(&self, p: &Path) {
+        self.vectors.remove(p);
+        if let Some((_, old)) = self.docs.remove(p) {
+            self.unlink(p, &old.links);
+            for t in old.terms.keys() {
+                if let Some(mut n) = self.df.get_mut(t) {
+                    *n = n.saturating_sub(1);
+                }
+                self.df.remove_if(t, |_, n| *n == 0);
+            }
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // the words don't change, the relative links are resolved from the new folder
+    pub fn rename(&self, s: &Path, d: &Path) {
+        if let Some((_, mut doc)) = self.docs.remove(s) {
+            self.remove(d);
+            self.unlink(s, &doc.links);
+            doc.resolve(d);
+            self.link(d, &doc.links);
+            self.docs.insert(d.to_path_buf(), doc);
+            if let Some((_, v)) = self.vectors.remove(s) {
+                self.vectors.insert(d.to_path_buf(), v);
+            }
+        }
+    }
+
+    // f is given the links of p, none when unknown
+    pub fn with_links<R>(&self, p: &Path, f: impl FnOnce(&HashSet<LinkKey>) -> R) -> R {
+        match self.docs.get(p) {
+            Some(d) => f(&d.links),
+            None => f(&HashSet::new()),
+        }
+    }
+
+    pub fn links(&self, p: &Path) -> HashSet<LinkKey> {
+        self.with_links(p, |links| links.clone())
+    }
+
+    // the articles with one of the links
+    pub fn linking<'a>(&self, links: impl IntoIterator<Item = &'a LinkKey>) -> HashSet<PathBuf> {
+        links
+            .into_iter()
+            .filter_map(|l| self.linking.get(l).map(|pp| pp.value().clone()))
+            .flatten()
+            .collect()
+    }
+
+    fn link(&self, p: &Path, links: &HashSet<LinkKey>) {
+        for l in links {
+            self.linking
+                .entry(l.clone())
+                .or_default()
+                .insert(p.to_path_buf());
+        }
+    }
+
+    fn unlink(&self, p: &Path, links: &HashSet<LinkKey>) {
+        for l in links {
+            if let Some(mut pp) = self.linking.get_mut(l) {
+                pp.remove(p);
+            }
+            self.linking.remove_if(l, |_, pp| pp.is_empty());
+        }
+    }
+
+    // the cosine of p with each article sharing a word with it
+    pub fn similar(&self, p: &Path) -> HashMap<PathBuf, f64> {
+        // before any ref is held on the docs
+        let version = (self.version.load(Ordering::SeqCst), self.docs.len());
+        let a = match self.docs.get(p) {
+            Some(doc) => self.vector(p, &doc, version),
+            None => return HashMap::new(),
+        };
+        self.docs
+            .iter()
+            .filter(|other| other.key() != p)
+            .filter_map(|other| {
+                let b = self.vector(other.key(), other.value(), version);
+                let (small, large) = if a.len() < b.len() {
+                    (&a, &b)
+                } else {
+                    (&b, &a)
+                };
+                let dot: f64 = small
+                    .iter()
+                    .filter_map(|(t, w)| large.get(t).map(|v| w * v))
+                    .sum();
+                if dot <= 0.0 {
+                    return None;
+                }
+                Some((other.key().clone(), dot))
+            })
+            .collect()
+    }
+
+    // computed again once the df changed ; version : with the number of articles
+    fn vector(&self, p: &Path, doc: &Doc, (version, articles): (usize, usize)) -> Vector {
+        if let Some(v) = self.vectors.get(p).filter(|v| v.0 == version) {
+            return v.1.clone();
+        }
+        let articles = articles as f64;
+        let weights: HashMap<String, f64> = doc
+            .terms
+            .iter()
+            .map(|(t, n)| {
+                let df = self.df.get(t).map_or(0, |n| *n) as f64;
+                let idf = ((1.0 + articles) / (1.0 + df)).ln() + 1.0;
+                (t.clone(), (1.0 + (*n as f64).ln()) * idf)
+            })
+            .collect();
+        let norm = norm(weights.values().copied());
+        let v: Vector = Arc::new(weights.into_iter().map(|(t, w)| (t, w / norm)).collect());
+        self.vectors.insert(p.to_path_buf(), (version, v.clone()));
+        v
+    }
+}
+
+fn norm(weights: impl Iterator<Item = f64>) -> f64 {
+    weights.map(|w| w * w).sum::<f64>().sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tf_idf() -> std::io::Result<()> {
+        let s = Similarity::default();
+        let doc = |content: &str| Doc::parse(Path::new("/n/a.md"), content);
+        s.set(
+            Path::new("/n/a.md"),
+            doc("---\ntitle: a\n---\nThe borrow checker and the lifetimes, [b](b.md)\n"),
+        );
+        s.set(
+            Path::new("/n/b.md"),
+            doc("lifetimes and borrow checker errors\n\n```\nlet x = 1;\n```\n"),
+        );
+        s.set(
+            Path::new("/n/c.md"),
+            doc("lifetimes of [[Plants]] in the garden"),
+        );
+        s.set(Path::new("/n/d.md"), doc("the and with"));
+
+        let similar = s.similar(Path::new("/n/a.md"));
+        assert!(similar[Path::new("/n/b.md")] > similar[Path::new("/n/c.md")]);
+        assert!(!similar.contains_key(Path::new("/n/d.md")));
+        assert_eq!(
+            HashSet::from([LinkKey::Path("/n/b.md".into())]),
+            s.links(Path::new("/n/a.md"))
+        );
+        assert_eq!(
+            HashSet::from([LinkKey::Wiki("plants".into())]),
+            s.links(Path::new("/n/c.md"))
+        );
+
+        s.rename(Path::new("/n/b.md"), Path::new("/n/e.md"));
+        s.remove(Path::new("/n/c.md"));
+        let similar = s.similar(Path::new("/n/a.md"));
+        assert_eq!(
+            vec![&PathBuf::from("/n/e.md")],
+            similar.keys().collect::<Vec<_>>()
+        );
+        assert!(!s.df.contains_key("garden"));
+        assert_eq!(
+            HashSet::from([PathBuf::from("/n/a.md")]),
+            s.linking(&[LinkKey::Path("/n/b.md".into())])
+        );
+        assert!(s.linking(&[LinkKey::Wiki("plants".into())]).is_empty());
+        assert_eq!(Some(2), s.df.get("lifetimes").map(|n| *n));
+        Ok(())
+    }
+
+    #[test]
+    fn cached_weights() -> std::io::Result<()> {
+        let texts = [
+            ("/n/a.md", "borrow checker lifetimes"),
+            ("/n/b.md", "lifetimes and borrow errors"),
+            ("/n/c.md", "garden lifetimes"),
+        ];
+        let fill = |s: &Similarity, texts: &[(&str, &str)]| {
+            for (p, content) in texts {
+                s.set(Path::new(p), Doc::parse(Path::new(p), content));
+            }
+        };
+        // summed in the order of the maps
+        let close = |a: HashMap<PathBuf, f64>, b: HashMap<PathBuf, f64>| {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(p, x)| b.get(p).is_some_and(|y| (x - y).abs() < 1e-9))
+        };
+        let cached = Similarity::default();
+        fill(&cached, &texts[..2]);
+        let before = cached.similar(Path::new("/n/a.md"));
+
+        // the df changed : the weights computed before are stale
+        fill(&cached, &texts[2..]);
+        let fresh = Similarity::default();
+        fill(&fresh, &texts);
+        let a = Path::new("/n/a.md");
+        assert!(close(fresh.similar(a), cached.similar(a)));
+        assert!(!close(before, cached.similar(a)));
+
+        // moved : the same words, the relative links from the new folder
+        cached.set(a, Doc::parse(a, "borrow checker lifetimes [b](b.md) [[c]]"));
+        cached.rename(a, Path::new("/m/a.md"));
+        assert_eq!(
+            HashSet::from([LinkKey::Path("/m/b.md".into()), LinkKey::Wiki("c".into())]),
+            cached.links(Path::new("/m/a.md"))
+        );
+        fresh.rename(a, Path::new("/m/a.md"));
+        let moved = Path::new("/m/a.md");
+        assert!(close(fresh.similar(moved), cached.similar(moved)));
+        Ok(())
+    }
+}
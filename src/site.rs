@@ -0,0 +1,152 @@
+// the pages of the static export ; the links between them are relative, the site can be
+// served from any folder or opened from the disk
+use crate::renderer;
+use pulldown_cmark::escape::escape_html;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+// an entry of search.json
+#[derive(Debug, Serialize)]
+pub struct SearchEntry {
+    pub id: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub headings: Vec<String>,
+    pub url: String, // relative to the site
+}
+
+pub fn article_path(id: &str) -> String {
+    format!("articles/{}.html", safe_path(id))
+}
+
+pub fn asset_path(rel_id: &str) -> String {
+    format!("assets/{}", safe_path(rel_id))
+}
+
+// one page per tag, the names are unique even if two tags look alike once cleaned
+pub fn tag_paths<'a>(tags: impl Iterator<Item = &'a String>) -> BTreeMap<String, String> {
+    let mut used = HashSet::new();
+    let mut paths = BTreeMap::new();
+    for tag in tags {
+        if paths.contains_key(tag) {
+            continue;
+        }
+        let base: String = tag
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        let mut name = base.clone();
+        let mut n = 1;
+        while !used.insert(name.clone()) {
+            name = format!("{}-{}", base, n);
+            n += 1;
+        }
+        paths.insert(tag.clone(), format!("tags/{}.html", name));
+    }
+    paths
+}
+
+// the url of the page `to` from the page `from`, both relative to the site
+pub fn relative_url(from: &str, to: &str) -> String {
+    let up = "../".repeat(from.matches('/').count());
+    format!("{}{}", up, renderer::percent_encode(to))
+}
+
+pub fn article_page(title: &str, tags: &[(&str, String)], index_url: &str, body: &str) -> String {
+    let tags: String = tags
+        .iter()
+        .map(|(tag, url)| format!("<a class=\"tag\" href=\"{}\">{}</a> ", url, escape(tag)))
+        .collect();
+    page(
+        title,
+        &format!(
+            "<nav><a href=\"{}\">index</a></nav>\n<h1>{}</h1>\n<p>{}</p>\n<article>\n{}</article>\n",
+            index_url,
+            escape(title),
+            tags,
+            body
+        ),
+    )
+}
+
+// links : (text, url)
+pub fn list_page(
+    title: &str,
+    index_url: Option<&str>,
+    sections: &[(&str, Vec<(String, String)>)],
+) -> String {
+    let mut content = String::new();
+    if let Some(url) = index_url {
+        content.push_str(&format!("<nav><a href=\"{}\">index</a></nav>\n", url));
+    }
+    content.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+    for (name, links) in sections {
+        content.push_str(&format!("<h2>{}</h2>\n<ul>\n", escape(name)));
+        for (text, url) in links {
+            content.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                url,
+                escape(text)
+            ));
+        }
+        content.push_str("</ul>\n");
+    }
+    page(title, &content)
+}
+
+fn page(title: &str, content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+<title>{}</title>\n\
+<style>body {{ max-width: 50em; margin: auto; padding: 1em; font-family: sans-serif }} \
+.tag {{ margin-right: .5em }}</style>\n\
+</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        content
+    )
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    let _ = escape_html(&mut escaped, s);
+    escaped
+}
+
+// ids come from the headers, they must stay inside the output folder
+fn safe_path(id: &str) -> String {
+    id.split('/')
+        .map(|s| match s {
+            "" | "." | ".." => "_",
+            s => s,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_and_urls() -> std::io::Result<()> {
+        assert_eq!(
+            "articles/rust/ownership.html",
+            article_path("rust/ownership")
+        );
+        assert_eq!("articles/_/_/etc.html", article_path("../../etc"));
+        assert_eq!(
+            "../../tags/a%20b.html",
+            relative_url("articles/rust/x.html", "tags/a b.html")
+        );
+        assert_eq!("index.html", relative_url("index.html", "index.html"));
+
+        let tags = ["C++".to_string(), "c--".into(), "C++".into(), "web".into()];
+        let paths = tag_paths(tags.iter());
+        assert_eq!("tags/c--.html", paths["C++"]);
+        assert_eq!("tags/c---1.html", paths["c--"]);
+        assert_eq!("tags/web.html", paths["web"]);
+        Ok(())
+    }
+}
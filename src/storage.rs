@@ -1,57 +1,635 @@
-use crate::domain::{ArtRef, TaggedArticle};
+use crate::domain::{ArtRef, RelatedArticle, RelatedTag, Rules, TagCount, TagRules, TaggedArticle};
+use crate::link_graph::{LinkGraph, Outline};
+use crate::similarity::{Doc, LinkKey, Similarity};
 use dashmap::DashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
+use std::time::SystemTime;
+
+#[derive(Clone, Debug)]
+struct Mount {
+    root: String, // name of the root folder
+    at: PathBuf,  // where the folder appears in the root (symlink targets)
+}
+
+// where the articles and their tags are kept : in memory, or on disk for the vaults too
+// large for it (see DiskBackend) ; the roots and the rules stay in the Store
+pub trait Backend: Debug + Send + Sync {
+    fn insert(&self, m: &TaggedArticle); // replaces the article of the same path
+    fn remove(&self, p: &Path) -> Option<TaggedArticle>;
+    fn update_path(&self, s: &Path, m: &TaggedArticle); // the article of s moved to m
+    fn get_by_path(&self, p: &Path) -> Option<TaggedArticle>;
+    fn get_by_id(&self, id: &str) -> Option<TaggedArticle>; // the first path on collisions
+    fn get_all_articles(&self) -> Vec<TaggedArticle>;
+    fn get_by_tag(&self, tag: &str) -> Vec<ArtRef>;
+    fn get_all_tags(&self) -> Vec<String>;
+
+    fn get_all_paths(&self) -> Vec<PathBuf> {
+        self.get_all_articles()
+            .into_iter()
+            .map(|m| m.art.path)
+            .collect()
+    }
+
+    fn get_by_root(&self, root: &str) -> Vec<ArtRef> {
+        self.get_all_articles()
+            .into_iter()
+            .filter(|a| a.art.root == root)
+            .map(|a| a.art)
+            .collect()
+    }
+
+    // the articles a wiki link to target may lead to (see ArtRef::is_named)
+    fn get_by_name(&self, target: &str) -> Vec<TaggedArticle> {
+        self.get_all_articles()
+            .into_iter()
+            .filter(|m| m.art.is_named(target))
+            .collect()
+    }
+
+    // what was read from the file of an article : only kept by the backends surviving
+    // the restarts, the unchanged files aren't read again (see Store::unchanged)
+    fn set_parsed(&self, _p: &Path, _parsed: &Parsed) {}
+
+    fn get_parsed(&self, _p: &Path) -> Option<Parsed> {
+        None
+    }
+}
+
+// an article as read from its file : the header as written (the store places it in the
+// roots), then its words, links and headings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parsed {
+    pub meta: TaggedArticle,
+    pub doc: Doc,
+    pub outline: Outline,
+    pub modified: Option<SystemTime>, // of the file, taken before reading it
+    #[serde(skip)]
+    pub kept: bool, // taken back from the backend
+}
 
 #[derive(Clone, Debug)]
 pub struct Store {
-    by_tag: Arc<DashMap<String, Vec<ArtRef>>>,
-    by_path: Arc<DashMap<PathBuf, TaggedArticle>>,
+    backend: Arc<dyn Backend>,
+    roots: Arc<DashMap<PathBuf, Mount>>, // canonical folders articles are read from
+    root_paths: Arc<DashMap<String, PathBuf>>, // the configured root folders
+    rules: Arc<RwLock<Rules>>,
+    cooccurrences: Arc<OnceLock<Cooccurrences>>, // counted when first needed
+    similarity: Arc<Similarity>, // in memory, filled again at startup from what was parsed
+    graph: Arc<LinkGraph>,       // likewise
+    writing: Arc<Mutex<()>>,     // the co-occurrences follow the changes of the backend
+    files: Arc<Mutex<()>>,       // see lock_files
+}
+
+// how much the text, the tags and the links count in the related articles
+const TEXT_WEIGHT: f64 = 0.5;
+const TAGS_WEIGHT: f64 = 0.3;
+const LINKS_WEIGHT: f64 = 0.2;
+
+// how many articles bear two tags together, counts[a][a] : how many bear a
+#[derive(Debug, Default)]
+struct Cooccurrences {
+    counts: DashMap<String, HashMap<String, usize>>,
+    articles: AtomicUsize,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::new()
+    }
 }
 
 impl Store {
     pub fn new() -> Store {
+        Store::with_backend(MemoryBackend::default())
+    }
+
+    pub fn with_backend<B: Backend + 'static>(backend: B) -> Store {
         Store {
-            by_tag: Arc::new(DashMap::new()),
-            by_path: Arc::new(DashMap::new()),
+            backend: Arc::new(backend),
+            roots: Arc::new(DashMap::new()),
+            root_paths: Arc::new(DashMap::new()),
+            rules: Arc::new(RwLock::new(Rules::default())),
+            cooccurrences: Arc::new(OnceLock::new()),
+            similarity: Arc::new(Similarity::default()),
+            graph: Arc::new(LinkGraph::default()),
+            writing: Arc::new(Mutex::new(())),
+            files: Arc::new(Mutex::new(())),
         }
     }
 
+    // all the write operations are idempotent : events can be replayed or reach the store
+    // for paths it never saw (eg: a temp file removed before being parsed)
+
     pub fn insert(&self, m: &TaggedArticle) {
-        self.by_path.insert(m.art.path.clone(), m.clone());
+        let _writing = self.lock();
+        if let Some(other) = self
+            .backend
+            .get_by_id(&m.art.id)
+            .filter(|o| o.art.path != m.art.path)
+        {
+            warn!(
+                "{} and {} share the id \"{}\"",
+                other.art.path.display(),
+                m.art.path.display(),
+                m.art.id
+            );
+        }
+        let old = self.backend.get_by_path(&m.art.path);
+        if old.as_ref() == Some(m) {
+            return; // eg: an unchanged file at startup
+        }
+        if let (Some(old), Some(c)) = (old, self.cooccurrences.get()) {
+            c.remove(&old.tags);
+        }
+        self.backend.insert(m);
+        if let Some(c) = self.cooccurrences.get() {
+            c.add(&m.tags);
+        }
+    }
 
-        let _ = m
-            .tags
+    pub fn remove(&self, p: &Path) {
+        let _writing = self.lock();
+        self.forget(p);
+        self.unmount(p);
+    }
+
+    pub fn update_path(&self, s: &Path, d: &Path) {
+        if s == d {
+            return;
+        }
+
+        let _writing = self.lock();
+        let v = match self.backend.get_by_path(s) {
+            Some(v) => v,
+            None => {
+                self.forget(s);
+                self.unmount(s);
+                return;
+            }
+        };
+        // the destination may have been replaced by the move
+        self.forget(d);
+
+        // an id from the front matter follows the file
+        let explicit = v.art.id != self.default_id(&v.art.root, &v.art.rel_path);
+        let (root, rel_path) = self.locate(d).unwrap_or((v.art.root, v.art.rel_path));
+        let mut new_art = ArtRef::new(d.to_path_buf(), &v.art.title);
+        new_art.id = if explicit {
+            v.art.id
+        } else {
+            self.default_id(&root, &rel_path)
+        };
+        new_art.root = root;
+        new_art.rel_path = rel_path;
+        new_art.aliases = v.art.aliases;
+        self.backend
+            .update_path(s, &TaggedArticle::new_from_art(&new_art, &v.tags));
+        self.similarity.rename(s, d);
+        self.graph.rename(s, d);
+    }
+
+    pub fn update_meta(&self, m: &TaggedArticle) {
+        self.insert(m);
+    }
+
+    // the words, links and headings of an article of the store
+    pub fn index_content(&self, p: &Path, parsed: Parsed) {
+        let _writing = self.lock();
+        if self.backend.get_by_path(p).is_some() {
+            if !parsed.kept {
+                self.backend.set_parsed(p, &parsed);
+            }
+            self.similarity.set(p, parsed.doc);
+            self.graph.set(p, parsed.outline);
+        }
+    }
+
+    // what was read from the file of p when it hasn't been modified since, eg: at startup
+    // with a backend kept on disk ; p is expected to be canonical
+    pub fn unchanged(&self, p: &Path) -> Option<Parsed> {
+        let modified = fs::metadata(p).and_then(|m| m.modified()).ok()?;
+        let mut parsed = self.backend.get_parsed(p)?;
+        if parsed.modified != Some(modified) {
+            return None;
+        }
+        parsed.kept = true;
+        Some(parsed)
+    }
+
+    // the articles of the backend whose file is gone or now ignored, eg: removed while the
+    // app wasn't running ; the roots must be known
+    pub fn forget_missing(&self) {
+        for p in self.backend.get_all_paths() {
+            if !p.is_file() || self.locate(&p).is_none() {
+                self.remove(&p);
+            }
+        }
+    }
+
+    pub fn add_root(&self, p: &Path, name: &str) {
+        self.root_paths.insert(name.to_owned(), p.to_path_buf());
+        self.add_link_target(p, name, Path::new(""));
+    }
+
+    // a folder or file reached through a symlink of the given root, found at `at` in it ;
+    // the targets already in the roots (loops, shortcuts) keep their place
+    pub fn add_link_target(&self, p: &Path, name: &str, at: &Path) {
+        if !at.as_os_str().is_empty() && self.locate(p).is_some() {
+            return;
+        }
+        self.roots.insert(
+            p.to_path_buf(),
+            Mount {
+                root: name.to_owned(),
+                at: at.to_path_buf(),
+            },
+        );
+    }
+
+    // set before the roots are read
+    pub fn set_rules(&self, rules: Rules) {
+        if let Ok(mut r) = self.rules.write() {
+            *r = rules;
+        }
+    }
+
+    // rel : relative to the root
+    pub fn ignores(&self, rel: &Path) -> bool {
+        self.rules.read().is_ok_and(|r| r.ignore.ignores(rel))
+    }
+
+    pub fn tag_rules(&self) -> TagRules {
+        self.rules
+            .read()
+            .map(|r| r.tags.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn normalize_tags(&self, tags: &[String]) -> Vec<String> {
+        self.tag_rules().normalize(tags)
+    }
+
+    // the tag as it is indexed, empty when nothing is left of it
+    pub fn normalize_tag(&self, tag: &str) -> String {
+        self.normalize_tags(&[tag.to_string()])
+            .pop()
+            .unwrap_or_default()
+    }
+
+    pub fn root_path(&self, name: &str) -> Option<PathBuf> {
+        self.root_paths.get(name).map(|p| p.value().clone())
+    }
+
+    pub fn root_of(&self, p: &Path) -> Option<String> {
+        self.locate(p).map(|(root, _)| root)
+    }
+
+    // the root name and the path relative to it (None when ignored),
+    // p is expected to be canonical, the deepest root containing it wins
+    pub fn locate(&self, p: &Path) -> Option<(String, PathBuf)> {
+        self.roots
             .iter()
-            .map(|t| self.add_to_tag(t, &m.art))
-            .collect::<Vec<()>>();
+            .filter(|r| p.starts_with(r.key()))
+            .max_by_key(|r| r.key().components().count())
+            .and_then(|r| {
+                let rel = p.strip_prefix(r.key()).ok()?;
+                Some((r.value().root.clone(), r.value().at.join(rel)))
+            })
+            .filter(|(_, rel)| !self.ignores(rel))
     }
 
-    pub fn remove(&self, p: &PathBuf) {
-        let remove_result = self.by_path.remove(&p.clone()).unwrap();
-        let removed_meta = remove_result.1;
+    // the path relative to the root without its extension
+    pub fn default_id(&self, root: &str, rel_path: &Path) -> String {
+        self.rel_id(root, &rel_path.with_extension(""))
+    }
 
-        let _ = removed_meta
-            .tags
+    // a path relative to the root, prefixed by the root name when there are several roots
+    pub fn rel_id(&self, root: &str, rel_path: &Path) -> String {
+        let mut parts: Vec<String> = rel_path
             .iter()
-            .map(|t| {
-                self.remove_from_tag(t, &removed_meta.art);
+            .map(|c| c.to_string_lossy().to_string())
+            .collect();
+        if self.root_paths.len() > 1 {
+            parts.insert(0, root.to_owned());
+        }
+        parts.join("/")
+    }
+
+    // a file of the roots by its path relative to the root, written like the ids
+    // (prefixed by the root name when there are several roots, it may be with one too)
+    pub fn resolve_rel(&self, rel: &str) -> Option<PathBuf> {
+        let prefixed = rel
+            .split_once('/')
+            .and_then(|(root, rel)| Some(self.root_path(root)?.join(rel)));
+        if self.root_paths.len() > 1 {
+            return prefixed;
+        }
+        let root = self.root_paths.iter().next()?.value().clone();
+        match prefixed {
+            Some(p) if !root.join(rel).exists() => Some(p),
+            _ => Some(root.join(rel)),
+        }
+    }
+
+    pub fn get_by_path(&self, p: &Path) -> Option<TaggedArticle> {
+        self.backend.get_by_path(p)
+    }
+
+    pub fn get_by_id(&self, id: &str) -> Option<TaggedArticle> {
+        self.backend.get_by_id(id)
+    }
+
+    // the links and anchors of an article
+    pub fn outline(&self, p: &Path) -> Option<Outline> {
+        self.graph.get(p)
+    }
+
+    pub fn has_anchor(&self, p: &Path, slug: &str) -> bool {
+        self.graph.has_anchor(p, slug)
+    }
+
+    pub fn is_allowed(&self, p: &Path) -> bool {
+        self.root_of(p).is_some()
+    }
+
+    pub fn get_all_roots(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.root_paths.iter().map(|r| r.key().clone()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn get_by_root(&self, root: &str) -> Vec<ArtRef> {
+        self.backend.get_by_root(root)
+    }
+
+    pub fn get_by_name(&self, target: &str) -> Vec<TaggedArticle> {
+        self.backend.get_by_name(target)
+    }
+
+    pub fn get_all_articles(&self) -> Vec<TaggedArticle> {
+        self.backend.get_all_articles()
+    }
+
+    pub fn get_by_tag(&self, tag: &str) -> Vec<ArtRef> {
+        self.backend.get_by_tag(tag)
+    }
+
+    pub fn get_all_tags(&self) -> Vec<String> {
+        self.backend.get_all_tags()
+    }
+
+    // by tag
+    pub fn tag_counts(&self) -> Vec<TagCount> {
+        let mut counts: Vec<TagCount> = self
+            .cooccurrences()
+            .counts
+            .iter()
+            .map(|row| TagCount {
+                tag: row.key().clone(),
+                count: row.get(row.key()).copied().unwrap_or(0),
             })
-            .collect::<Vec<()>>();
+            .collect();
+        counts.sort_by(|a, b| a.tag.cmp(&b.tag));
+        counts
     }
 
-    pub fn update_path(&self, s: &PathBuf, d: &PathBuf) {
-        if let Some(v) = self.by_path.get(s) {
-            let new_meta = TaggedArticle::new(d.clone(), &v.art.clone().title, &v.clone().tags);
-            self.by_path.insert(d.clone(), new_meta.clone());
-            self.update_path_for_tags(&v.tags.clone(), &v.art.path.clone(), &new_meta.art);
-            {}
+    // the tags found along this one, the most frequent first
+    pub fn related_tags(&self, tag: &str) -> Vec<RelatedTag> {
+        // cloned : no ref is held on the map while reading the other rows
+        let cooccurrences = self.cooccurrences();
+        let row = match cooccurrences.counts.get(tag) {
+            Some(row) => row.value().clone(),
+            None => return vec![],
+        };
+        let articles = cooccurrences.articles.load(atomic::Ordering::SeqCst) as f64;
+        let with_tag = row.get(tag).copied().unwrap_or(0) as f64;
+
+        let mut related: Vec<RelatedTag> = row
+            .into_iter()
+            .filter(|(other, _)| other != tag)
+            .map(|(other, count)| {
+                let with_other = cooccurrences.count(&other) as f64;
+                let lift = count as f64 * articles / (with_tag * with_other);
+                RelatedTag {
+                    tag: other,
+                    count,
+                    lift,
+                    pmi: lift.log2(),
+                }
+            })
+            .collect();
+        related.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(b.lift.partial_cmp(&a.lift).unwrap_or(Ordering::Equal))
+                .then(a.tag.cmp(&b.tag))
+        });
+        related
+    }
+
+    // the k articles closest to the one of p, by their words, tags and links
+    pub fn related_articles(&self, p: &Path, k: usize) -> Vec<RelatedArticle> {
+        let m = match self.backend.get_by_path(p) {
+            Some(m) => m,
+            None => return vec![],
+        };
+        let texts = self.similarity.similar(p);
+        let links = self.similarity.links(p);
+        let tags: HashSet<&String> = m.tags.iter().collect();
+
+        // only the articles with a score : sharing words, tags or links, linked either way
+        let mut candidates: HashSet<PathBuf> = texts.keys().cloned().collect();
+        for t in &m.tags {
+            candidates.extend(self.backend.get_by_tag(t).into_iter().map(|a| a.path));
         }
-        self.by_path.remove(s);
+        for l in &links {
+            match l {
+                LinkKey::Path(target) => {
+                    candidates.insert(target.clone());
+                }
+                LinkKey::Wiki(target) => candidates.extend(
+                    self.backend
+                        .get_by_name(target)
+                        .into_iter()
+                        .map(|o| o.art.path),
+                ),
+            }
+        }
+        let to_m = std::iter::once(LinkKey::Path(p.to_path_buf()))
+            .chain(m.art.names().into_iter().map(LinkKey::Wiki))
+            .collect::<Vec<_>>();
+        candidates.extend(self.similarity.linking(links.iter().chain(&to_m)));
+        candidates.remove(p);
+
+        let mut related: Vec<RelatedArticle> = candidates
+            .iter()
+            .filter_map(|c| self.backend.get_by_path(c))
+            .map(|o| {
+                // the link sets are compared in place
+                let link_score = self.similarity.with_links(&o.art.path, |other_links| {
+                    let linked = links.iter().any(|l| l.leads_to(&o.art))
+                        || other_links.iter().any(|l| l.leads_to(&m.art));
+                    if linked {
+                        1.0
+                    } else {
+                        jaccard(&links, other_links)
+                    }
+                });
+                let r = RelatedArticle {
+                    text: texts.get(&o.art.path).copied().unwrap_or(0.0),
+                    tags: jaccard(&tags, &o.tags.iter().collect()),
+                    links: link_score,
+                    score: 0.0,
+                    art: o.art,
+                };
+                let score = TEXT_WEIGHT * r.text + TAGS_WEIGHT * r.tags + LINKS_WEIGHT * r.links;
+                RelatedArticle { score, ..r }
+            })
+            .filter(|r| r.score > 0.0)
+            .collect();
+        related.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then(a.art.id.cmp(&b.art.id))
+        });
+        related.truncate(k);
+        related
     }
 
-    pub fn update_meta(&self, m: &TaggedArticle) {
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.writing.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // the articles the backend holds (eg: on disk, from a previous run) are counted once,
+    // then the counts follow the writes
+    fn cooccurrences(&self) -> &Cooccurrences {
+        if let Some(c) = self.cooccurrences.get() {
+            return c;
+        }
+        let _writing = self.lock();
+        self.cooccurrences.get_or_init(|| {
+            let c = Cooccurrences::default();
+            for m in self.backend.get_all_articles() {
+                c.add(&m.tags);
+            }
+            c
+        })
+    }
+
+    // a write to the files of the roots is checked (eg: against the etag) then done without
+    // interleaving ; the stores of the vaults write apart
+    pub fn lock_files(&self) -> MutexGuard<'_, ()> {
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // p was a symlink : the folder or file it led to isn't part of the root anymore,
+    // nor its articles (unless reachable otherwise)
+    fn unmount(&self, p: &Path) {
+        let (root, rel) = match self.locate(p) {
+            Some(found) => found,
+            None => return,
+        };
+        let mounts = self.roots.len();
+        self.roots.retain(|_, m| {
+            m.root != root || m.at.as_os_str().is_empty() || !m.at.starts_with(&rel)
+        });
+        if self.roots.len() == mounts {
+            return;
+        }
+        for p in self.backend.get_all_paths() {
+            if self.locate(&p).is_none() {
+                self.forget(&p);
+            }
+        }
+    }
+
+    fn forget(&self, p: &Path) {
+        if let (Some(old), Some(c)) = (self.backend.remove(p), self.cooccurrences.get()) {
+            c.remove(&old.tags);
+        }
+        self.similarity.remove(p);
+        self.graph.remove(p);
+    }
+}
+
+// what two sets share, over what they hold
+fn jaccard<T: Eq + std::hash::Hash>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    match a.union(b).count() {
+        0 => 0.0,
+        all => a.intersection(b).count() as f64 / all as f64,
+    }
+}
+
+impl Cooccurrences {
+    fn add(&self, tags: &[String]) {
+        self.change(tags, true);
+    }
+
+    fn remove(&self, tags: &[String]) {
+        self.change(tags, false);
+    }
+
+    fn change(&self, tags: &[String], add: bool) {
+        let tags: BTreeSet<&String> = tags.iter().collect();
+        for a in &tags {
+            let mut row = self.counts.entry(a.to_string()).or_default();
+            for b in &tags {
+                let count = row.entry(b.to_string()).or_insert(0);
+                *count = if add {
+                    *count + 1
+                } else {
+                    count.saturating_sub(1)
+                };
+            }
+            row.retain(|_, count| *count > 0);
+        }
+        for a in &tags {
+            self.counts.remove_if(*a, |_, row| row.is_empty());
+        }
+
+        if add {
+            self.articles.fetch_add(1, atomic::Ordering::SeqCst);
+        } else {
+            let _ = self.articles.fetch_update(
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::SeqCst,
+                |n| n.checked_sub(1),
+            );
+        }
+    }
+
+    fn count(&self, tag: &str) -> usize {
+        self.counts
+            .get(tag)
+            .and_then(|row| row.get(tag).copied())
+            .unwrap_or(0)
+    }
+}
+
+// the default backend : three maps, by path, by tag and by id
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    by_tag: DashMap<String, Vec<ArtRef>>,
+    by_path: DashMap<PathBuf, TaggedArticle>,
+    by_id: DashMap<String, BTreeSet<PathBuf>>, // several when the ids collide
+}
+
+impl Backend for MemoryBackend {
+    fn insert(&self, m: &TaggedArticle) {
         let art = m.clone().art;
+        if let Some(old) = self.by_path.get(&art.path).map(|o| o.art.id.clone()) {
+            self.remove_from_id(&old, &art.path);
+        }
+        self.add_to_id(&art.id, &art.path);
 
         if let Some(mut found_meta) = self.by_path.get_mut(&art.path) {
             let tags_to_insert = m.tags.iter().filter(|t| !found_meta.tags.contains(t));
@@ -88,27 +666,83 @@ impl Store {
 
             *found_meta = m.clone();
         } else {
+            self.add(m);
         }
     }
 
-    pub fn get_all_articles(&self) -> Vec<TaggedArticle> {
+    fn remove(&self, p: &Path) -> Option<TaggedArticle> {
+        let (_, removed_meta) = self.by_path.remove(p)?;
+        self.remove_from_id(&removed_meta.art.id, p);
+        for t in &removed_meta.tags {
+            self.remove_from_tag(t, &removed_meta.art);
+        }
+        Some(removed_meta)
+    }
+
+    fn update_path(&self, s: &Path, m: &TaggedArticle) {
+        if let Some(old) = self.by_path.get(s).map(|o| o.art.id.clone()) {
+            self.remove_from_id(&old, s);
+        }
+        self.add_to_id(&m.art.id, &m.art.path);
+        self.by_path.insert(m.art.path.clone(), m.clone());
+        self.update_path_for_tags(&m.tags, s, &m.art);
+        self.by_path.remove(s);
+    }
+
+    fn get_by_path(&self, p: &Path) -> Option<TaggedArticle> {
+        self.by_path.get(p).map(|a| a.value().clone())
+    }
+
+    // the first path when the ids collide
+    fn get_by_id(&self, id: &str) -> Option<TaggedArticle> {
+        let p = self.by_id.get(id)?.iter().next()?.clone();
+        self.get_by_path(&p)
+    }
+
+    fn get_all_articles(&self) -> Vec<TaggedArticle> {
         self.by_path.iter().map(|a| a.value().clone()).collect()
     }
 
-    pub fn get_by_tag(&self, tag: &str) -> Vec<ArtRef> {
+    fn get_all_paths(&self) -> Vec<PathBuf> {
+        self.by_path.iter().map(|a| a.key().clone()).collect()
+    }
+
+    fn get_by_tag(&self, tag: &str) -> Vec<ArtRef> {
         if let Some(kv) = self.by_tag.get(tag) {
             return kv.value().clone();
         }
         vec![]
     }
 
-    pub fn get_all_tags(&self) -> Vec<String> {
+    fn get_all_tags(&self) -> Vec<String> {
         self.by_tag.iter().map(|a| a.key().clone()).collect()
     }
+}
+
+impl MemoryBackend {
+    fn add(&self, m: &TaggedArticle) {
+        self.by_path.insert(m.art.path.clone(), m.clone());
 
-    //
-    // privates
-    //
+        let _ = m
+            .tags
+            .iter()
+            .map(|t| self.add_to_tag(t, &m.art))
+            .collect::<Vec<()>>();
+    }
+
+    fn add_to_id(&self, id: &str, p: &Path) {
+        self.by_id
+            .entry(id.to_owned())
+            .or_default()
+            .insert(p.to_path_buf());
+    }
+
+    fn remove_from_id(&self, id: &str, p: &Path) {
+        if let Some(mut paths) = self.by_id.get_mut(id) {
+            paths.remove(p);
+        }
+        self.by_id.remove_if(id, |_, paths| paths.is_empty());
+    }
 
     fn add_to_tag(&self, t: &str, art: &ArtRef) {
         match self.by_tag.get_mut(t) {
@@ -129,19 +763,13 @@ impl Store {
         self.by_tag.remove_if(t, |_, tag_vec| tag_vec.is_empty());
     }
 
-    fn update_path_for_tags(
-        &self,
-        tags_to_update: &[String],
-        old_path: &PathBuf,
-        new_art: &ArtRef,
-    ) {
+    fn update_path_for_tags(&self, tags_to_update: &[String], old_path: &Path, new_art: &ArtRef) {
         tags_to_update
             .iter()
             .map(|t| {
                 self.by_tag.alter(t, |_, v| {
-                    vec![new_art.clone()]
-                        .iter()
-                        .chain(v.iter().filter(|m| m.path != old_path.clone()))
+                    std::iter::once(new_art)
+                        .chain(v.iter().filter(|m| m.path != old_path))
                         .cloned()
                         .collect()
                 })
@@ -153,11 +781,17 @@ impl Store {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::disk_backend::DiskBackend;
+    use tempfile::tempdir;
 
     fn art(i: i32) -> ArtRef {
         ArtRef {
             path: format!("path_{}", i).into(),
             title: format!("title_{}", i),
+            root: String::new(),
+            id: format!("path_{}", i),
+            rel_path: format!("path_{}", i).into(),
+            aliases: vec![],
         }
     }
 
@@ -165,15 +799,23 @@ mod tests {
         t.iter().map(|i| format!("tag_{}", i)).collect()
     }
 
+    // the same test against every backend
+    fn each_backend(test: impl Fn(Store) -> std::io::Result<()>) -> std::io::Result<()> {
+        test(Store::new())?;
+        let dir = tempdir()?;
+        test(Store::with_backend(DiskBackend::open(dir.path())?))
+    }
+
     #[test]
     fn new() -> std::io::Result<()> {
-        let s = Store::new();
-        {
-            // initialized empty
-            assert_eq!(0, s.by_path.len());
-            assert_eq!(0, s.by_tag.len());
-        }
-        Ok(())
+        each_backend(|s| {
+            {
+                // initialized empty
+                assert_eq!(0, s.get_all_articles().len());
+                assert_eq!(0, s.get_all_tags().len());
+            }
+            Ok(())
+        })
     }
 
     #[test]
@@ -191,36 +833,37 @@ mod tests {
         new.tags = tags(vec![3, 2]);
         new.art.title = "title_2".to_string();
 
-        let s = Store::new();
-        s.insert(&old);
+        each_backend(|s| {
+            s.insert(&old);
 
-        s.update_meta(&new.clone());
+            s.update_meta(&new.clone());
 
-        {
-            // articles update
-            assert_eq!(new, *s.by_path.get(&old.art.path).unwrap());
-        }
-        {
-            // tags update
-
-            assert!(s.by_tag.get(&t1).is_none());
-            assert!(s.by_tag.get(&t2).is_some());
-            assert!(s.by_tag.get(&t3).is_some());
-
-            let _ = s
-                .by_tag
-                .iter()
-                .flat_map(|t| {
-                    t.iter()
-                        .filter(|t| t.path == new.art.path)
-                        .cloned()
-                        .collect::<Vec<ArtRef>>()
-                })
-                .map(|m| assert_eq!(new.art.clone(), m))
-                .collect::<Vec<()>>();
-        }
+            {
+                // articles update
+                assert_eq!(new, s.get_by_path(&old.art.path).unwrap());
+            }
+            {
+                // tags update
 
-        Ok(())
+                assert!(s.get_by_tag(&t1).is_empty());
+                assert!(!s.get_by_tag(&t2).is_empty());
+                assert!(!s.get_by_tag(&t3).is_empty());
+
+                let _ = s
+                    .get_all_tags()
+                    .iter()
+                    .flat_map(|t| {
+                        s.get_by_tag(t)
+                            .into_iter()
+                            .filter(|t| t.path == new.art.path)
+                            .collect::<Vec<ArtRef>>()
+                    })
+                    .map(|m| assert_eq!(new.art.clone(), m))
+                    .collect::<Vec<()>>();
+            }
+
+            Ok(())
+        })
     }
 
     #[test]
@@ -234,39 +877,39 @@ mod tests {
             tags: tags(vec![3, 2]),
         };
 
-        let s = Store::new();
-
-        {
-            // simple insertion works
-            s.insert(&m1);
-            assert_eq!(1, s.by_path.len());
-            assert_eq!(2, s.by_tag.len());
-        }
+        each_backend(|s| {
+            {
+                // simple insertion works
+                s.insert(&m1);
+                assert_eq!(1, s.get_all_articles().len());
+                assert_eq!(2, s.get_all_tags().len());
+            }
 
-        {
-            // duplicate tags (tag2) should append metadata
-            s.insert(&m2);
-            assert_eq!(2, s.by_path.len());
-            assert_eq!(3, s.by_tag.len());
-        }
+            {
+                // duplicate tags (tag2) should append metadata
+                s.insert(&m2);
+                assert_eq!(2, s.get_all_articles().len());
+                assert_eq!(3, s.get_all_tags().len());
+            }
 
-        {
-            // articles aren't altered at insertion
-            assert_eq!(m1, *s.by_path.get(&m1.art.path).unwrap());
-            assert_eq!(m2, *s.by_path.get(&m2.art.path).unwrap());
-            assert_ne!(m1, *s.by_path.get(&m2.art.path).unwrap());
-        }
+            {
+                // articles aren't altered at insertion
+                assert_eq!(m1, s.get_by_path(&m1.art.path).unwrap());
+                assert_eq!(m2, s.get_by_path(&m2.art.path).unwrap());
+                assert_ne!(m1, s.get_by_path(&m2.art.path).unwrap());
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    fn count_path_found_all_tags(s: &Store, p: &PathBuf) -> usize {
-        s.by_tag
+    fn count_path_found_all_tags(s: &Store, p: &Path) -> usize {
+        s.get_all_tags()
             .iter()
             .map(|t| {
-                t.iter()
-                    .filter(|t| &t.path == p)
-                    .cloned()
+                s.get_by_tag(t)
+                    .into_iter()
+                    .filter(|t| t.path == p)
                     .collect::<Vec<ArtRef>>()
                     .len()
             })
@@ -286,25 +929,26 @@ mod tests {
             tags: tags(vec![2, 3]),
         };
 
-        let s = Store::new();
-        s.insert(&m1.clone());
-        s.insert(&m2);
+        each_backend(|s| {
+            s.insert(&m1.clone());
+            s.insert(&m2);
 
-        let to_remove = m1.art.path.clone();
-        s.remove(&to_remove);
+            let to_remove = m1.art.path.clone();
+            s.remove(&to_remove);
 
-        {
-            //articles
-            assert!(s.by_path.get(&m1.art.path).is_none());
-            assert!(s.by_path.get(&m2.art.path).is_some());
-        }
+            {
+                //articles
+                assert!(s.get_by_path(&m1.art.path).is_none());
+                assert!(s.get_by_path(&m2.art.path).is_some());
+            }
 
-        {
-            //tags
-            assert_eq!(0, count_path_found_all_tags(&s.clone(), &m1.art.path));
-            assert_eq!(2, count_path_found_all_tags(&s.clone(), &m2.art.path));
-        }
-        Ok(())
+            {
+                //tags
+                assert_eq!(0, count_path_found_all_tags(&s.clone(), &m1.art.path));
+                assert_eq!(2, count_path_found_all_tags(&s.clone(), &m2.art.path));
+            }
+            Ok(())
+        })
     }
 
     #[test]
@@ -324,55 +968,312 @@ mod tests {
         let mut new_meta = art1.clone();
         new_meta.path = new_path.clone();
 
-        let s = Store::new();
+        each_backend(|s| {
+            {
+                // preconditions
+                s.insert(&m1);
+                s.insert(&m2);
 
-        {
-            // preconditions
-            s.insert(&m1);
-            s.insert(&m2);
+                assert_eq!(2, s.get_all_articles().len());
+                assert_eq!(3, s.get_all_tags().len());
+            }
 
-            assert_eq!(2, s.by_path.len());
-            assert_eq!(3, s.by_tag.len());
-        }
+            s.update_path(&m1.art.path.clone(), &new_path.clone());
 
-        s.update_path(&m1.art.path.clone(), &new_path.clone());
+            {
+                // check articles update
+                assert!(s.get_by_path(&m1.art.path).is_none());
+                assert!(s.get_by_path(&m2.art.path).is_some());
+                assert!(s.get_by_path(&new_path).is_some());
+            }
+            {
+                // check tags update
 
-        {
-            // check articles update
-            assert!(s.by_path.get(&m1.art.path).is_none());
-            assert!(s.by_path.get(&m2.art.path).is_some());
-            assert!(s.by_path.get(&new_path).is_some());
-        }
-        {
-            // check tags update
+                // m1 path updated for tag1 (it's alone)
+                assert_eq!(Some(&new_meta), s.get_by_tag("tag_1").first());
 
-            // m1 path updated for tag1 (it's alone)
-            assert_eq!(Some(&new_meta), s.by_tag.get("tag_1").unwrap().first());
+                // m1 path updated for tag2
+                assert_eq!(
+                    Some(&&new_meta),
+                    s.get_by_tag("tag_2")
+                        .iter()
+                        .filter(|t| t.title == art1.title)
+                        .collect::<Vec<&ArtRef>>()
+                        .first()
+                );
 
-            // m1 path updated for tag2
-            assert_eq!(
-                Some(&&new_meta),
-                s.by_tag
-                    .get("tag_2")
-                    .unwrap()
+                // m1 not added to other tag
+                assert!(s
+                    .get_by_tag("tag_3")
                     .iter()
                     .filter(|t| t.title == art1.title)
                     .collect::<Vec<&ArtRef>>()
-                    .first()
-            );
+                    .is_empty());
+            }
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn idempotent_operations() -> std::io::Result<()> {
+        let m1 = TaggedArticle {
+            art: art(1),
+            tags: tags(vec![1, 2]),
+        };
+        let unknown: PathBuf = "unknown".into();
+
+        each_backend(|s| {
+            {
+                // unknown paths are ignored
+                s.remove(&unknown);
+                s.update_path(&unknown, &m1.art.path);
+                assert_eq!(0, s.get_all_articles().len());
+                assert_eq!(0, s.get_all_tags().len());
+            }
+
+            {
+                // inserting twice replaces the article
+                s.insert(&m1);
+                s.insert(&m1);
+                assert_eq!(1, s.get_all_articles().len());
+                assert_eq!(2, count_path_found_all_tags(&s, &m1.art.path));
+            }
+
+            {
+                // updating an unknown article inserts it
+                let m2 = TaggedArticle {
+                    art: art(2),
+                    tags: tags(vec![3]),
+                };
+                s.update_meta(&m2);
+                assert_eq!(Some(m2.clone()), s.get_by_path(&m2.art.path));
+                assert_eq!(vec![m2.art.clone()], s.get_by_tag("tag_3"));
+            }
+
+            {
+                // moving onto a known path replaces it
+                s.update_path(&art(2).path, &m1.art.path);
+                assert_eq!(1, s.get_all_articles().len());
+                assert!(s.get_by_tag("tag_1").is_empty());
+                assert_eq!(art(2).title, s.get_by_path(&m1.art.path).unwrap().art.title);
+            }
+
+            {
+                // removing twice is harmless
+                s.remove(&m1.art.path);
+                s.remove(&m1.art.path);
+                assert_eq!(0, s.get_all_articles().len());
+                assert_eq!(0, s.get_all_tags().len());
+            }
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn colliding_ids() -> std::io::Result<()> {
+        each_backend(|s| {
+            let with_id = |i, id: &str| {
+                let mut a = art(i);
+                a.id = id.into();
+                TaggedArticle::new_from_art(&a, &[])
+            };
+            s.insert(&with_id(2, "same"));
+            s.insert(&with_id(1, "same"));
+            assert_eq!(art(1).path, s.get_by_id("same").unwrap().art.path);
 
-            // m1 not added to other tag
-            assert!(s
-                .by_tag
-                .get("tag_3")
-                .unwrap()
-                .iter()
-                .filter(|t| t.title == art1.title)
-                .collect::<Vec<&ArtRef>>()
-                .first()
-                .is_none());
+            s.insert(&with_id(1, "other"));
+            assert_eq!(art(2).path, s.get_by_id("same").unwrap().art.path);
+            s.update_path(&art(2).path, &art(3).path);
+            assert_eq!(art(3).path, s.get_by_id("same").unwrap().art.path);
+            s.remove(&art(3).path);
+            assert!(s.get_by_id("same").is_none());
+            assert_eq!(art(1).path, s.get_by_id("other").unwrap().art.path);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn by_root_and_name() -> std::io::Result<()> {
+        each_backend(|s| {
+            let mut a = art(1);
+            a.root = "notes".into();
+            a.rel_path = "sub/Page.md".into();
+            a.aliases = vec!["Other".into()];
+            s.insert(&TaggedArticle::new_from_art(&a, &[]));
+            s.insert(&TaggedArticle::new_from_art(&art(2), &[]));
+
+            let paths = |arts: Vec<ArtRef>| arts.into_iter().map(|a| a.path).collect::<Vec<_>>();
+            assert_eq!(vec![a.path.clone()], paths(s.get_by_root("notes")));
+            for name in &["sub/page", "page", "title_1", "other"] {
+                assert_eq!(1, s.get_by_name(name).len(), "{}", name);
+            }
+            assert!(s.get_by_name("sub").is_empty());
+
+            s.update_path(&a.path, &art(3).path);
+            assert_eq!(vec![art(3).path], paths(s.get_by_root("notes")));
+            assert_eq!(art(3).path, s.get_by_name("other")[0].art.path);
+            s.remove(&art(3).path);
+            assert!(s.get_by_root("notes").is_empty());
+            assert!(s.get_by_name("page").is_empty());
+            Ok(())
+        })
+    }
+
+    // how often all the articles were loaded
+    #[derive(Debug, Default)]
+    struct Loads(MemoryBackend, Arc<AtomicUsize>);
+
+    impl Backend for Loads {
+        fn insert(&self, m: &TaggedArticle) {
+            self.0.insert(m)
+        }
+        fn remove(&self, p: &Path) -> Option<TaggedArticle> {
+            self.0.remove(p)
+        }
+        fn update_path(&self, s: &Path, m: &TaggedArticle) {
+            self.0.update_path(s, m)
         }
+        fn get_by_path(&self, p: &Path) -> Option<TaggedArticle> {
+            self.0.get_by_path(p)
+        }
+        fn get_by_id(&self, id: &str) -> Option<TaggedArticle> {
+            self.0.get_by_id(id)
+        }
+        fn get_all_articles(&self) -> Vec<TaggedArticle> {
+            self.1.fetch_add(1, atomic::Ordering::SeqCst);
+            self.0.get_all_articles()
+        }
+        fn get_by_tag(&self, tag: &str) -> Vec<ArtRef> {
+            self.0.get_by_tag(tag)
+        }
+        fn get_all_tags(&self) -> Vec<String> {
+            self.0.get_all_tags()
+        }
+    }
+
+    #[test]
+    fn cooccurrences_counted_once() -> std::io::Result<()> {
+        let backend = Loads::default();
+        let counter = backend.1.clone();
+        let loads = || counter.load(atomic::Ordering::SeqCst);
+        backend.insert(&TaggedArticle::new_from_art(&art(1), &tags(vec![1, 2])));
+        let s = Store::with_backend(backend);
+        s.insert(&TaggedArticle::new_from_art(&art(2), &tags(vec![1])));
+        assert_eq!(0, loads());
+
+        assert_eq!(2, s.tag_counts()[0].count);
+        s.remove(&art(1).path);
+        let counts: Vec<(String, usize)> = s
+            .tag_counts()
+            .into_iter()
+            .map(|c| (c.tag, c.count))
+            .collect();
+        assert_eq!(vec![("tag_1".to_string(), 1)], counts);
+        assert_eq!(1, loads());
+        Ok(())
+    }
+
+    #[test]
+    fn cooccurrences() -> std::io::Result<()> {
+        each_backend(|s| {
+            let with = |i, t: &[&str]| {
+                let t: Vec<String> = t.iter().map(|t| t.to_string()).collect();
+                TaggedArticle::new_from_art(&art(i), &t)
+            };
+            s.insert(&with(1, &["x", "y"]));
+            s.insert(&with(2, &["x", "y", "z"]));
+            s.insert(&with(3, &["x"]));
+            s.insert(&with(4, &["z"]));
+
+            let related = s.related_tags("x");
+            assert_eq!(
+                vec![("y", 2), ("z", 1)],
+                related
+                    .iter()
+                    .map(|r| (r.tag.as_str(), r.count))
+                    .collect::<Vec<_>>()
+            );
+            // 2 of the 3 x are y, like 2 of the 4 articles
+            assert!((related[0].lift - 4.0 / 3.0).abs() < 1e-9);
+            assert!((related[1].lift - 2.0 / 3.0).abs() < 1e-9);
+            assert!(related[1].pmi < 0.0);
+            assert!(s.related_tags("nope").is_empty());
+
+            // updated, moved, removed
+            s.update_meta(&with(3, &["x", "z"]));
+            s.update_path(&art(1).path, &art(2).path);
+            s.remove(&art(4).path);
+            s.remove(&art(4).path);
+            assert_eq!(
+                vec![("y".to_string(), 1), ("z".to_string(), 1)],
+                s.related_tags("x")
+                    .into_iter()
+                    .map(|r| (r.tag, r.count))
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec![("x", 2), ("y", 1), ("z", 1)],
+                s.tag_counts()
+                    .iter()
+                    .map(|t| (t.tag.as_str(), t.count))
+                    .collect::<Vec<_>>()
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn ids() -> std::io::Result<()> {
+        let s = Store::new();
+        s.add_root(Path::new("/notes"), "notes");
+        s.add_link_target(Path::new("/elsewhere"), "notes", Path::new("ext"));
+
+        let located = s.locate(Path::new("/elsewhere/rust/a.md")).unwrap();
+        assert_eq!(("notes".into(), PathBuf::from("ext/rust/a.md")), located);
+        assert_eq!("ext/rust/a", s.default_id("notes", &located.1));
+        assert_eq!(
+            Some(PathBuf::from("/notes/ext/b.png")),
+            s.resolve_rel("ext/b.png")
+        );
+        assert_eq!(
+            Some(PathBuf::from("/notes/ext/b.png")),
+            s.resolve_rel("notes/ext/b.png")
+        );
+
+        let mut a = ArtRef::new("/notes/a.md".into(), "a");
+        a.root = "notes".into();
+        a.rel_path = "a.md".into();
+        a.id = "a".into();
+        let mut b = ArtRef::new("/notes/b.md".into(), "b");
+        b.root = "notes".into();
+        b.rel_path = "b.md".into();
+        b.id = "from-front-matter".into();
+        s.insert(&TaggedArticle::new_from_art(&a, &[]));
+        s.insert(&TaggedArticle::new_from_art(&b, &[]));
+
+        // the default id follows the path, not the explicit one
+        s.update_path(Path::new("/notes/a.md"), Path::new("/notes/sub/c.md"));
+        s.update_path(Path::new("/notes/b.md"), Path::new("/notes/sub/d.md"));
+        assert_eq!(
+            Path::new("/notes/sub/c.md"),
+            s.get_by_id("sub/c").unwrap().art.path
+        );
+        assert!(s.get_by_id("a").is_none());
+        assert_eq!(
+            Path::new("/notes/sub/d.md"),
+            s.get_by_id("from-front-matter").unwrap().art.path
+        );
 
+        // the root name comes first with several roots
+        s.add_root(Path::new("/wiki"), "wiki");
+        assert_eq!("wiki/x/y", s.default_id("wiki", Path::new("x/y.md")));
+        assert_eq!(
+            Some(PathBuf::from("/wiki/x/y.png")),
+            s.resolve_rel("wiki/x/y.png")
+        );
+        assert_eq!(None, s.resolve_rel("other/x/y.png"));
         Ok(())
     }
 }
@@ -0,0 +1,141 @@
+// https : the certificate and key (pem) given in the settings, or a self-signed pair made on
+// the first run and kept for the next ones, so the browsers only have to accept it once
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{NoClientAuth, ServerConfig};
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+pub struct Https {
+    pub config: ServerConfig,
+    pub http_port: Option<u16>, // redirected to https
+}
+
+pub fn server_config(cert: &Path, key: &Path) -> io::Result<ServerConfig> {
+    let chain = certs(&mut BufReader::new(fs::File::open(cert)?))
+        .ok()
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| invalid(cert, "no certificate found"))?;
+
+    // pkcs8 ("PRIVATE KEY") or pkcs1 ("RSA PRIVATE KEY")
+    let pem = fs::read(key)?;
+    let mut keys = pkcs8_private_keys(&mut pem.as_slice()).unwrap_or_default();
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut pem.as_slice()).unwrap_or_default();
+    }
+    let key_der = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid(key, "no private key found"))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(chain, key_der)
+        .map_err(|e| invalid(cert, e))?;
+    Ok(config)
+}
+
+// the cert.pem and key.pem of dir, made for these host names (or ips) if missing
+pub fn self_signed(dir: &Path, names: &[String]) -> io::Result<(PathBuf, PathBuf)> {
+    let cert = dir.join("cert.pem");
+    let key = dir.join("key.pem");
+    if cert.is_file() && key.is_file() {
+        return Ok((cert, key));
+    }
+
+    let mut params = CertificateParams::default();
+    params.subject_alt_names = names
+        .iter()
+        .map(|n| match n.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(n.clone()),
+        })
+        .collect();
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CommonName, "knowledge (self-signed)");
+    params.distinguished_name = dn;
+    let generated = Certificate::from_params(params).map_err(io::Error::other)?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(&cert, generated.serialize_pem().map_err(io::Error::other)?)?;
+    write_private(&key, &generated.serialize_private_key_pem())?;
+    Ok((cert, key))
+}
+
+#[cfg(unix)]
+fn write_private(p: &Path, content: &str) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(p)?
+        .write_all(content.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(p: &Path, content: &str) -> io::Result<()> {
+    fs::write(p, content)
+}
+
+// where a request of the plain http port is sent ; host : the Host header, its port replaced
+pub fn https_url(host: &str, port: u16, path: &str) -> String {
+    let name = match host.rsplit_once(':') {
+        // a bare ipv6 address has several colons
+        Some((h, p)) if (!h.contains(':') || h.ends_with(']')) && p.parse::<u16>().is_ok() => h,
+        _ => host,
+    };
+    match port {
+        443 => format!("https://{}{}", name, path),
+        p => format!("https://{}:{}{}", name, p, path),
+    }
+}
+
+fn invalid<E: ToString>(p: &Path, e: E) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{}: {}", p.display(), e.to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn self_signed_once() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let tls = dir.path().join("tls");
+        let names = vec!["localhost".to_string(), "127.0.0.1".into()];
+        let (cert, key) = self_signed(&tls, &names)?;
+        let pem = fs::read_to_string(&cert)?;
+        assert!(pem.starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(server_config(&cert, &key).is_ok());
+
+        // kept for the next runs
+        self_signed(&tls, &names)?;
+        assert_eq!(pem, fs::read_to_string(&cert)?);
+
+        assert!(server_config(&key, &key).is_err());
+        assert!(server_config(&cert, &cert).is_err());
+        assert!(server_config(&cert, &dir.path().join("nope.pem")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn redirections() -> std::io::Result<()> {
+        assert_eq!(
+            "https://kb.lan:8443/api/tags?x=1",
+            https_url("kb.lan:8080", 8443, "/api/tags?x=1")
+        );
+        assert_eq!("https://kb.lan/", https_url("kb.lan", 443, "/"));
+        assert_eq!("https://[::1]:8443/", https_url("[::1]:8080", 8443, "/"));
+        assert_eq!("https://[::1]:8443/", https_url("[::1]", 8443, "/"));
+        Ok(())
+    }
+}
@@ -1,48 +1,275 @@
-use crate::domain::{FileEvent, FileOp};
+use crate::domain::{FileEvent, FileOp, Root, SymlinkPolicy};
+use crate::event_coalescer;
+use crate::file_watcher::STOP_CHECK;
+use crate::storage::Store;
 use async_std::{
+    future,
     sync::{Receiver, Sender},
     task,
 };
-use std::fs;
+use dashmap::DashSet;
+use std::fs::{self, DirEntry};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct Dir {
+    pub path: PathBuf,
+    pub root: String, // name of the root folder it belongs to
+    pub rel: PathBuf, // where it is in the root folder
+    pub links: u8,    // symlinks followed to reach this directory
+}
+
+impl Dir {
+    pub fn root(r: &Root) -> Self {
+        Dir {
+            path: r.path.clone(),
+            root: r.name.clone(),
+            rel: PathBuf::new(),
+            links: 0,
+        }
+    }
+}
+
+// until stopped, the traversals under way end by themselves
+pub fn watch(
+    dir_rcv: &Receiver<Dir>,
+    dir_send: &Sender<Dir>,
+    fe_send: &Sender<FileEvent>,
+    policy: SymlinkPolicy,
+    store: Arc<Store>,
+    stopped: &AtomicBool,
+) {
+    // canonical paths of the traversed directories : a directory reachable
+    // through several paths (symlink loops, shared folders) is traversed once
+    let visited: Arc<DashSet<PathBuf>> = Arc::new(DashSet::new());
 
-pub fn watch(dir_rcv: &Receiver<PathBuf>, dir_send: &Sender<PathBuf>, fe_send: &Sender<FileEvent>) {
     task::block_on(async {
-        loop {
-            if let Ok(p) = dir_rcv.recv().await {
-                let dc = Sender::clone(dir_send);
-                let fc = Sender::clone(fe_send);
-                task::spawn(async move { traverse_tree(&p.clone(), &dc, &fc).await });
-            };
+        while !stopped.load(Ordering::SeqCst) {
+            match future::timeout(STOP_CHECK, dir_rcv.recv()).await {
+                Ok(Ok(d)) => {
+                    let dc = Sender::clone(dir_send);
+                    let fc = Sender::clone(fe_send);
+                    let vc = visited.clone();
+                    let sc = store.clone();
+                    task::spawn(async move { traverse_tree(&d, &vc, policy, &sc, &dc, &fc).await });
+                }
+                Ok(Err(_)) => break,
+                Err(_) => continue, // nothing to traverse for now
+            }
         }
     });
 }
 
-async fn traverse_tree(dir: &PathBuf, dir_send: &Sender<PathBuf>, fe_send: &Sender<FileEvent>) {
-    if dir.is_dir() {
-        let ee = match fs::read_dir(dir) {
-            Err(_) => return,
-            Ok(ee) => ee,
-        };
+async fn traverse_tree(
+    dir: &Dir,
+    visited: &DashSet<PathBuf>,
+    policy: SymlinkPolicy,
+    store: &Store,
+    dir_send: &Sender<Dir>,
+    fe_send: &Sender<FileEvent>,
+) {
+    if !first_visit(dir, visited) {
+        return;
+    }
 
-        for entry in ee {
-            let entry = match entry {
-                Ok(e) => e,
-                Err(_) => continue, // we just skip the entry in case of problem
-            };
+    let ee = match fs::read_dir(&dir.path) {
+        Err(_) => return,
+        Ok(ee) => ee,
+    };
 
-            let path = entry.path();
-            if path.is_dir() {
-                dir_send.send(path).await;
-            } else {
+    // we just skip the entry in case of problem
+    for entry in ee.flatten() {
+        match visit_entry(&entry, dir, policy, store) {
+            Some(Entry::Dir(d)) => dir_send.send(d).await,
+            Some(Entry::File(path)) => {
                 fe_send
                     .send(FileEvent {
                         op: FileOp::Create,
                         path,
                         dst: None,
                     })
-                    .await;
+                    .await
+            }
+            None => {}
+        }
+    }
+}
+
+// the files of a root, found synchronously with the same rules as the traversal
+pub fn walk(root: &Root, policy: SymlinkPolicy, store: &Store) -> Vec<PathBuf> {
+    let visited = DashSet::new();
+    let mut files = vec![];
+    let mut dirs = vec![Dir::root(root)];
+
+    while let Some(dir) = dirs.pop() {
+        if !first_visit(&dir, &visited) {
+            continue;
+        }
+        let ee = match fs::read_dir(&dir.path) {
+            Err(_) => continue,
+            Ok(ee) => ee,
+        };
+        for entry in ee.flatten() {
+            match visit_entry(&entry, &dir, policy, store) {
+                Some(Entry::Dir(d)) => dirs.push(d),
+                Some(Entry::File(path)) => files.push(path),
+                None => {}
+            }
+        }
+    }
+    files
+}
+
+enum Entry {
+    Dir(Dir),
+    File(PathBuf),
+}
+
+fn first_visit(dir: &Dir, visited: &DashSet<PathBuf>) -> bool {
+    match fs::canonicalize(&dir.path) {
+        Ok(real) if real.is_dir() => visited.insert(real),
+        _ => false,
+    }
+}
+
+fn visit_entry(entry: &DirEntry, dir: &Dir, policy: SymlinkPolicy, store: &Store) -> Option<Entry> {
+    let path = entry.path();
+    let rel = dir.rel.join(entry.file_name());
+    if event_coalescer::is_editor_file(&path) || store.ignores(&rel) {
+        return None;
+    }
+    let is_link = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+    let links = if is_link {
+        if !policy.follows(dir.links) {
+            return None;
+        }
+        dir.links + 1
+    } else {
+        dir.links
+    };
+
+    if is_link {
+        // the target, a folder or a file, becomes readable through the api
+        if let Ok(target) = fs::canonicalize(&path) {
+            store.add_link_target(&target, &dir.root, &rel);
+        }
+    }
+    if !path.is_dir() {
+        return Some(Entry::File(path));
+    }
+    Some(Entry::Dir(Dir {
+        path,
+        root: dir.root.clone(),
+        rel,
+        links,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::future::timeout;
+    use async_std::sync::channel;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    // root/a.md, root/sub/b.md, root/loop -> root, root/dup -> root/sub,
+    // root/ext -> ext, ext/c.md, ext/nested -> other, other/d.md, root/e.md -> e.md
+    fn setup(dir: &Path) -> std::io::Result<PathBuf> {
+        let root = dir.join("root");
+        let ext = dir.join("ext");
+        let other = dir.join("other");
+        for d in &[root.join("sub"), ext.clone(), other.clone()] {
+            fs::create_dir_all(d)?;
+        }
+        fs::write(root.join("a.md"), "")?;
+        fs::write(root.join("sub").join("b.md"), "")?;
+        fs::write(ext.join("c.md"), "")?;
+        fs::write(other.join("d.md"), "")?;
+        symlink(&root, root.join("loop"))?;
+        symlink(root.join("sub"), root.join("dup"))?;
+        symlink(&ext, root.join("ext"))?;
+        symlink(&other, ext.join("nested"))?;
+        fs::write(dir.join("e.md"), "")?;
+        symlink(dir.join("e.md"), root.join("e.md"))?;
+        Ok(root)
+    }
+
+    fn traverse(root: &Path, policy: SymlinkPolicy) -> (Vec<String>, Arc<Store>) {
+        let store = Arc::new(Store::new());
+        store.add_root(&fs::canonicalize(root).unwrap(), "root");
+        let (dir_send, dir_rcv) = channel(100);
+        let (fe_send, fe_rcv) = channel(100);
+        let ds = dir_send.clone();
+        let sc = store.clone();
+        thread::spawn(move || watch(&dir_rcv, &ds, &fe_send, policy, sc, &AtomicBool::new(false)));
+
+        task::block_on(async {
+            dir_send
+                .send(Dir::root(&Root::new(root.to_path_buf())))
+                .await;
+
+            let mut found = vec![];
+            while let Ok(Ok(fe)) = timeout(Duration::from_millis(300), fe_rcv.recv()).await {
+                found.push(fe.path.file_name().unwrap().to_string_lossy().into());
             }
+            found.sort();
+            (found, store)
+        })
+    }
+
+    #[test]
+    fn symlink_policies() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let root = setup(dir.path())?;
+        let ext = fs::canonicalize(dir.path().join("ext"))?;
+        let e = fs::canonicalize(dir.path().join("e.md"))?;
+
+        {
+            // loops and duplicated folders are traversed once
+            let (found, store) = traverse(&root, SymlinkPolicy::Follow);
+            assert_eq!(vec!["a.md", "b.md", "c.md", "d.md", "e.md"], found);
+            assert!(store.is_allowed(&ext.join("c.md")));
+            assert!(store.is_allowed(&e));
+
+            // the targets are out of reach once their symlink is gone
+            fs::remove_file(root.join("ext"))?;
+            fs::remove_file(root.join("e.md"))?;
+            store.remove(&fs::canonicalize(&root)?.join("ext"));
+            store.remove(&fs::canonicalize(&root)?.join("e.md"));
+            assert!(!store.is_allowed(&ext.join("c.md")));
+            assert!(!store.is_allowed(&ext.join("nested").join("d.md")));
+            assert!(!store.is_allowed(&e));
+            symlink(&ext, root.join("ext"))?;
+            symlink(&e, root.join("e.md"))?;
+        }
+        {
+            let (found, _) = traverse(&root, SymlinkPolicy::FollowOnce);
+            assert_eq!(vec!["a.md", "b.md", "c.md", "e.md"], found);
         }
+        {
+            let (found, store) = traverse(&root, SymlinkPolicy::Ignore);
+            assert_eq!(vec!["a.md", "b.md"], found);
+            assert!(!store.is_allowed(&ext.join("c.md")));
+            assert!(!store.is_allowed(&e));
+        }
+        {
+            // same rules when walking synchronously
+            let store = Store::new();
+            let mut found: Vec<String> = walk(&Root::new(root), SymlinkPolicy::FollowOnce, &store)
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into())
+                .collect();
+            found.sort();
+            assert_eq!(vec!["a.md", "b.md", "c.md", "e.md"], found);
+            assert!(store.is_allowed(&ext.join("c.md")));
+        }
+
+        Ok(())
     }
 }
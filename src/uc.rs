@@ -1,50 +1,251 @@
 use async_std::sync::{channel, Receiver, Sender};
 use async_std::task;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io;
 use std::iter::FromIterator;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 use storage::Store;
 
-use crate::domain::{ArtRef, Exp, FileEvent, MetadataEvent, Op};
+use crate::article_writer::{self, TagEdit};
+use crate::domain::{
+    check_root_names, ArtRef, Exp, FileEvent, Heading, Link, Op, RelatedArticle, Root, Rules,
+    SymlinkPolicy, TaggedArticle,
+};
 use crate::file_handler;
 use crate::file_watcher;
-use crate::metadata_handler;
+use crate::metadata_handler::{self, MetadataEvent};
+use crate::renderer::{self, Dest};
+use crate::site;
 use crate::storage;
-use crate::tree_traverser;
+use crate::tree_traverser::{self, Dir};
+
+fn build_graph_start_watcher(
+    roots: &[Root],
+    policy: SymlinkPolicy,
+    debounce: u64,
+    store: Arc<Store>,
+    stopped: Arc<AtomicBool>,
+) {
+    for r in roots {
+        if let Ok(real) = fs::canonicalize(&r.path) {
+            store.add_root(&real, &r.name);
+        }
+    }
+    // a backend kept on disk may know files removed since
+    store.forget_missing();
 
-pub fn build_graph_start_watcher(p: &PathBuf, store: &'static Store) {
     // the file chan is used by both file_watcher & build_graph
     let (file_send, file_rcv): (Sender<FileEvent>, Receiver<FileEvent>) = channel(100);
 
-    {
+    for r in roots {
         // file_watcher
-        let p_ = p.clone();
+        let p_ = r.path.clone();
         let file_send_ = file_send.clone();
-        thread::spawn(move || file_watcher::watch(&p_, &file_send_, 200));
+        let stopped_ = stopped.clone();
+        thread::spawn(move || file_watcher::watch(&p_, &file_send_, debounce, &stopped_));
     }
     {
         // build_graph
         let (meta_send, meta_rcv): (Sender<MetadataEvent>, Receiver<MetadataEvent>) = channel(100);
-        let (dir_send, dir_rcv): (Sender<PathBuf>, Receiver<PathBuf>) = channel(100);
+        let (dir_send, dir_rcv): (Sender<Dir>, Receiver<Dir>) = channel(100);
         let dir_send_ = dir_send.clone();
-        thread::spawn(move || tree_traverser::watch(&dir_rcv, &dir_send, &file_send));
-        thread::spawn(move || file_handler::watch(&file_rcv, &meta_send));
-        thread::spawn(move || metadata_handler::watch(&meta_rcv, store));
-        task::block_on(async { dir_send_.send(p.clone()).await });
+        let store_ = store.clone();
+        thread::spawn(move || {
+            tree_traverser::watch(&dir_rcv, &dir_send, &file_send, policy, store_, &stopped)
+        });
+        let store_ = store.clone();
+        thread::spawn(move || file_handler::watch(&file_rcv, &meta_send, &store_));
+        thread::spawn(move || metadata_handler::watch(&meta_rcv, &store));
+        task::block_on(async {
+            for r in roots {
+                dir_send_.send(Dir::root(r)).await;
+            }
+        });
     }
 }
 
-#[derive(Debug, Clone)]
+// a store kept up to date with the roots by the pipeline threads ; they run until stopped,
+// dropping the handle doesn't stop them (the store is usually shared with the server)
+pub struct Index {
+    store: Arc<Store>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Index {
+    pub fn store(&self) -> Arc<Store> {
+        self.store.clone()
+    }
+
+    // the watchers and the traversal stop within file_watcher::STOP_CHECK, the other
+    // threads once the events already sent are handled ; the store is kept as it is
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+// indexes the roots into the store then watches them, in the background
+pub fn index(
+    store: Store,
+    roots: &[Root],
+    policy: SymlinkPolicy,
+    debounce: u64,
+) -> io::Result<Index> {
+    check_root_names(roots).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let store = Arc::new(store);
+    let stopped = Arc::new(AtomicBool::new(false));
+    let roots = roots.to_vec();
+    let (store_, stopped_) = (store.clone(), stopped.clone());
+    thread::spawn(move || build_graph_start_watcher(&roots, policy, debounce, store_, stopped_));
+    Ok(Index { store, stopped })
+}
+
+// a store built once from the roots, without watching them
+pub fn scan(roots: &[Root], policy: SymlinkPolicy, rules: Rules) -> Store {
+    let store = Store::new();
+    store.set_rules(rules);
+    scan_into(&store, roots, policy);
+    store
+}
+
+// the roots read once into the store, eg: a backend kept on disk is brought up to date,
+// its unchanged files aren't read again
+pub fn scan_into(store: &Store, roots: &[Root], policy: SymlinkPolicy) {
+    for r in roots {
+        if let Ok(real) = fs::canonicalize(&r.path) {
+            store.add_root(&real, &r.name);
+        }
+    }
+    store.forget_missing();
+
+    for r in roots {
+        for p in tree_traverser::walk(r, policy, store) {
+            let p = match fs::canonicalize(&p) {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+            match file_handler::parse_changed(&p, store) {
+                Ok(parsed) => metadata_handler::index(parsed, store),
+                // the header may have been broken since the index was written
+                Err(_) => store.remove(&p),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Query {
     Sing(String),
+    Root(String), // all the articles of a root folder
     Comb(Op, Box<Query>, Box<Query>),
 }
 
+// `rust and (web or "programming languages")`, `root:notes and rust` ; "and" binds tighter
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = query_tokens(s)?.into_iter().peekable();
+        let q = query_or(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(q),
+            Some(t) => Err(format!("unexpected {:?} in the query", t)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Op(Op),
+    Word(String),
+}
+
+fn query_tokens(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("missing \"".into()),
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()\"".contains(*c)) {
+                    word.push(c);
+                }
+                tokens.push(match word.to_lowercase().as_str() {
+                    "and" => Token::Op(Op::And),
+                    "or" => Token::Op(Op::Or),
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<Token>>;
+
+fn query_or(tokens: &mut Tokens) -> Result<Query, String> {
+    let mut q = query_and(tokens)?;
+    while tokens.next_if_eq(&Token::Op(Op::Or)).is_some() {
+        q = Query::Comb(Op::Or, Box::new(q), Box::new(query_and(tokens)?));
+    }
+    Ok(q)
+}
+
+fn query_and(tokens: &mut Tokens) -> Result<Query, String> {
+    let mut q = query_atom(tokens)?;
+    while tokens.next_if_eq(&Token::Op(Op::And)).is_some() {
+        q = Query::Comb(Op::And, Box::new(q), Box::new(query_atom(tokens)?));
+    }
+    Ok(q)
+}
+
+fn query_atom(tokens: &mut Tokens) -> Result<Query, String> {
+    match tokens.next() {
+        Some(Token::Open) => {
+            let q = query_or(tokens)?;
+            match tokens.next() {
+                Some(Token::Close) => Ok(q),
+                _ => Err("missing )".into()),
+            }
+        }
+        Some(Token::Word(w)) => Ok(match w.strip_prefix("root:") {
+            Some(root) => Query::Root(root.into()),
+            None => Query::Sing(w),
+        }),
+        Some(t) => Err(format!("unexpected {:?} in the query", t)),
+        None => Err("incomplete query".into()),
+    }
+}
+
 pub fn search_by_tag(q: &Query, s: &Store) -> Vec<ArtRef> {
     fn new_exp(s: &Store, q: &Query) -> Exp<ArtRef> {
         match q {
-            Query::Sing(tag) => Exp::Sing(s.get_by_tag(tag).into_iter().collect()),
+            // the tags are looked for as they were indexed
+            Query::Sing(tag) => {
+                Exp::Sing(s.get_by_tag(&s.normalize_tag(tag)).into_iter().collect())
+            }
+            Query::Root(root) => Exp::Sing(s.get_by_root(root).into_iter().collect()),
             Query::Comb(op, q1, q2) => Exp::Comb(
                 op.clone(),
                 Box::new(new_exp(s, q1)),
@@ -56,21 +257,554 @@ pub fn search_by_tag(q: &Query, s: &Store) -> Vec<ArtRef> {
     Vec::from_iter(new_exp(s, q).reduce())
 }
 
-// todo : path is not checked, do not expose this publicly
-pub fn get_article_content(p: &str) -> std::io::Result<String> {
-    fs::read_to_string(p)
+// keeps the articles of the given root, if any
+pub fn filter_by_root<A: AsRef<ArtRef>>(arts: Vec<A>, root: &Option<String>) -> Vec<A> {
+    match root {
+        Some(root) => arts
+            .into_iter()
+            .filter(|a| &a.as_ref().root == root)
+            .collect(),
+        None => arts,
+    }
+}
+
+// articles are found by id, other files by their path relative to the root (written
+// like the ids), the base64 encoded absolute paths of the first versions still work
+pub fn resolve(id: &str, s: &Store) -> io::Result<String> {
+    if let Some(m) = s.get_by_id(id) {
+        return Ok(m.art.path.to_string_lossy().into());
+    }
+    if let Some(p) = s.resolve_rel(id).filter(|p| p.exists()) {
+        return Ok(p.to_string_lossy().into());
+    }
+    base64::decode(id)
+        .ok()
+        .and_then(|d| String::from_utf8(d).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown article"))
+}
+
+// relative links lead to the articles and assets of the api (its url : "/api", or
+// "/name/api" for a vault), wiki links to the first of their candidates
+pub fn render_article(p: &str, api: &str, s: &Store) -> io::Result<String> {
+    render_with(
+        p,
+        s,
+        // the html of the linked articles too, their anchors are in it
+        &|m| {
+            Some(format!(
+                "{}/articles/{}/html",
+                api,
+                renderer::percent_encode(&m.art.id)
+            ))
+        },
+        &|id| Some(format!("{}/assets/{}", api, renderer::percent_encode(id))),
+    )
+}
+
+// article_url : where the linked articles are, asset_url : where the other files
+// are, by their path relative to the root (written like the ids)
+fn render_with(
+    p: &str,
+    s: &Store,
+    article_url: &dyn Fn(&TaggedArticle) -> Option<String>,
+    asset_url: &dyn Fn(&str) -> Option<String>,
+) -> io::Result<String> {
+    let (_, content, base) = read_located(p, s)?;
+    Ok(renderer::render(&content, &|dest| match dest {
+        Dest::Path(dest) => match link_target(&base, dest, s)? {
+            Target::Article(m) => article_url(&m),
+            Target::File(rel) => asset_url(&s.rel_id(&base.0, &rel)),
+        },
+        Dest::Wiki(target) => wiki_candidates(target, s).first().and_then(article_url),
+    }))
+}
+
+// the link graph of an article : where its links lead, broken ones included ;
+// the links and anchors of the articles are kept by the store
+pub fn article_links(p: &str, s: &Store) -> io::Result<Vec<Link>> {
+    let (p, base) = locate_file(p, s)?;
+    let links = match s.outline(&p) {
+        Some(outline) => outline.links,
+        None => renderer::links(&fs::read_to_string(&p)?), // not an article
+    };
+
+    Ok(links
+        .into_iter()
+        .map(|dest| {
+            let wiki = renderer::wiki_dest(&dest);
+            let (path, anchor) = match &wiki {
+                Some((target, anchor)) => (target.clone(), anchor.clone()),
+                None => renderer::split_dest(&dest),
+            };
+            let mut candidates = vec![];
+            let target = if path.is_empty() {
+                // an anchor of the article itself
+                s.get_by_path(&p)
+            } else if wiki.is_some() {
+                candidates = wiki_candidates(&path, s);
+                match candidates.as_slice() {
+                    [m] => Some(m.clone()),
+                    _ => None,
+                }
+            } else {
+                match link_target(&base, &path, s) {
+                    Some(Target::Article(m)) => Some(m),
+                    _ => None,
+                }
+            };
+            let valid = match (&target, &anchor) {
+                (Some(m), Some(a)) => s.has_anchor(&m.art.path, a),
+                (Some(_), None) => true,
+                _ => false,
+            };
+            Link {
+                dest,
+                id: target.map(|m| m.art.id),
+                anchor,
+                valid,
+                // only reported when ambiguous
+                candidates: match candidates.len() {
+                    0 | 1 => vec![],
+                    _ => candidates.into_iter().map(|m| m.art.id).collect(),
+                },
+            }
+        })
+        .collect())
+}
+
+// the articles to read next, the closest first
+pub fn related_articles(p: &str, k: usize, s: &Store) -> io::Result<Vec<RelatedArticle>> {
+    let p = get_file(p, s)?;
+    if s.get_by_path(&p).is_none() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not an article"));
+    }
+    Ok(s.related_articles(&p, k))
+}
+
+// the content of the article, its root and its folder in the root
+fn read_located(p: &str, s: &Store) -> io::Result<(PathBuf, String, (String, PathBuf))> {
+    let (p, base) = locate_file(p, s)?;
+    let content = fs::read_to_string(&p)?;
+    Ok((p, content, base))
+}
+
+fn locate_file(p: &str, s: &Store) -> io::Result<(PathBuf, (String, PathBuf))> {
+    let p = get_file(p, s)?;
+    let (root, rel) = s
+        .locate(&p)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "outside of the roots"))?;
+    let dir = rel.parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok((p, (root, dir)))
+}
+
+enum Target {
+    Article(TaggedArticle),
+    File(PathBuf), // relative to the root
+}
+
+// base : the root and the folder of the linking article
+fn link_target(base: &(String, PathBuf), dest: &str, s: &Store) -> Option<Target> {
+    let rel = renderer::join_relative(&base.1, dest)?;
+    let path = fs::canonicalize(s.root_path(&base.0)?.join(&rel)).ok();
+    match path.and_then(|p| s.get_by_path(&p)) {
+        Some(m) => Some(Target::Article(m)),
+        None => Some(Target::File(rel)),
+    }
+}
+
+// the articles a wiki link may lead to, sorted by id (the case is ignored)
+fn wiki_candidates(target: &str, s: &Store) -> Vec<TaggedArticle> {
+    let target = renderer::wiki_target(target);
+    let mut found = s.get_by_name(&target);
+    found.sort_by(|a, b| a.art.id.cmp(&b.art.id));
+    found
+}
+
+// the number of pages and assets written
+#[derive(Debug, PartialEq)]
+pub struct ExportSummary {
+    pub articles: usize,
+    pub assets: usize,
+}
+
+// a static site of the articles selected by the query (all of them by default) :
+// their html, the tag pages, search.json and the assets they link to
+pub fn export_site(q: Option<&Query>, out: &Path, s: &Store) -> io::Result<ExportSummary> {
+    let mut arts: Vec<TaggedArticle> = match q {
+        Some(q) => search_by_tag(q, s)
+            .into_iter()
+            .filter_map(|a| s.get_by_path(&a.path))
+            .collect(),
+        None => s.get_all_articles(),
+    };
+    arts.sort_by(|a, b| a.art.id.cmp(&b.art.id));
+
+    let pages: HashMap<PathBuf, String> = arts
+        .iter()
+        .map(|m| (m.art.path.clone(), site::article_path(&m.art.id)))
+        .collect();
+    let tag_paths = site::tag_paths(arts.iter().flat_map(|m| m.tags.iter()));
+    let assets = RefCell::new(BTreeMap::new()); // out path -> source
+    let mut search = vec![];
+
+    for m in &arts {
+        let page = &pages[&m.art.path];
+        let body = render_with(
+            &m.art.path.to_string_lossy(),
+            s,
+            // the articles left out aren't linked
+            &|target| {
+                Some(match pages.get(&target.art.path) {
+                    Some(to) => site::relative_url(page, to),
+                    None => "#".into(),
+                })
+            },
+            &|id| {
+                let src = get_file(&s.resolve_rel(id)?.to_string_lossy(), s).ok()?;
+                let to = site::asset_path(id);
+                let url = site::relative_url(page, &to);
+                assets.borrow_mut().insert(to, src);
+                Some(url)
+            },
+        )?;
+        let tags: Vec<(&str, String)> = m
+            .tags
+            .iter()
+            .map(|t| (t.as_str(), site::relative_url(page, &tag_paths[t])))
+            .collect();
+        let index = site::relative_url(page, "index.html");
+        write_page(
+            out,
+            page,
+            &site::article_page(&m.art.title, &tags, &index, &body),
+        )?;
+
+        search.push(site::SearchEntry {
+            id: m.art.id.clone(),
+            title: m.art.title.clone(),
+            tags: m.tags.clone(),
+            headings: renderer::outline(&fs::read_to_string(&m.art.path)?)
+                .into_iter()
+                .map(|h| h.text)
+                .collect(),
+            url: page.clone(),
+        });
+    }
+
+    let links = |from: &str, arts: &mut dyn Iterator<Item = &TaggedArticle>| {
+        arts.map(|m| {
+            let url = site::relative_url(from, &pages[&m.art.path]);
+            (m.art.title.clone(), url)
+        })
+        .collect::<Vec<_>>()
+    };
+    for (tag, page) in &tag_paths {
+        let tagged = links(page, &mut arts.iter().filter(|m| m.tags.contains(tag)));
+        let index = site::relative_url(page, "index.html");
+        let content = site::list_page(tag, Some(&index), &[("Articles", tagged)]);
+        write_page(out, page, &content)?;
+    }
+
+    let tags = tag_paths
+        .iter()
+        .map(|(tag, page)| (tag.clone(), site::relative_url("index.html", page)))
+        .collect();
+    let mut by_title: Vec<&TaggedArticle> = arts.iter().collect();
+    by_title.sort_by_key(|m| m.art.title.to_lowercase());
+    let all = links("index.html", &mut by_title.into_iter());
+    let index = site::list_page("Knowledge", None, &[("Tags", tags), ("Articles", all)]);
+    write_page(out, "index.html", &index)?;
+    write_page(out, "search.json", &serde_json::to_string(&search)?)?;
+
+    let assets = assets.into_inner();
+    for (to, src) in &assets {
+        let to = out.join(to);
+        if let Some(dir) = to.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::copy(src, to)?;
+    }
+
+    Ok(ExportSummary {
+        articles: arts.len(),
+        assets: assets.len(),
+    })
+}
+
+fn write_page(out: &Path, page: &str, content: &str) -> io::Result<()> {
+    let p = out.join(page);
+    if let Some(dir) = p.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(p, content)
+}
+
+pub fn article_toc(p: &str, s: &Store) -> io::Result<Vec<Heading>> {
+    Ok(renderer::outline(&fs::read_to_string(get_file(p, s)?)?))
+}
+
+// a file of the roots, articles and assets are served the same way
+pub fn get_file(p: &str, s: &Store) -> io::Result<PathBuf> {
+    let p = check_path(p, s)?;
+    if !p.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a file"));
+    }
+    Ok(p)
+}
+
+//
+// writes : the files are written, the watcher then updates the store as usual
+//
+
+#[derive(Debug)]
+pub enum WriteError {
+    Io(io::Error),
+    Conflict(String), // the file changed since, its current etag
+    TooLarge,
+    UnsupportedType,
+}
+
+impl From<io::Error> for WriteError {
+    fn from(e: io::Error) -> Self {
+        WriteError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewArticle {
+    pub title: String,
+    pub tags: Vec<String>,
+    pub body: String,
+    pub root: Option<String>, // required when there are several roots
+    pub path: Option<String>, // relative to the root, built from the title by default
+}
+
+// the new article (as the store will know it) and its etag
+pub fn create_article(a: &NewArticle, s: &Store) -> Result<(ArtRef, String), WriteError> {
+    let name = match &a.root {
+        Some(name) => name.clone(),
+        None => match s.get_all_roots().as_slice() {
+            [name] => name.clone(),
+            _ => return Err(invalid_input("the root is required")),
+        },
+    };
+    let root = s
+        .root_path(&name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown root"))?;
+
+    let rel = PathBuf::from(match &a.path {
+        Some(p) => p.clone(),
+        None => article_writer::file_name(&a.title),
+    });
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(invalid_input("the path must be relative to the root"));
+    }
+
+    let id = s.default_id(&name, &rel);
+    let p = root.join(rel);
+    if let Some(dir) = p.parent() {
+        create_dir_in_roots(dir, s)?;
+    }
+
+    let _w = s.lock_files();
+    if p.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the file already exists").into());
+    }
+    // eg: the front matter id of another article, or the same name with another extension
+    if s.get_by_id(&id).is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the id is taken").into());
+    }
+    article_writer::write_atomic(
+        &p,
+        article_writer::new_article(&a.title, &a.tags, &a.body).as_bytes(),
+    )?;
+
+    let p = fs::canonicalize(p)?;
+    let etag = article_writer::etag(&p)?;
+    let art = metadata_handler::with_root(TaggedArticle::new(p, &a.title, &a.tags), s)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "outside of the roots"))?
+        .art;
+    Ok((art, etag))
+}
+
+// if_match : the If-Match header, the etags the content was based on ("*" for any),
+// the new etag is returned
+pub fn replace_article(
+    p: &str,
+    content: &str,
+    if_match: &str,
+    s: &Store,
+) -> Result<String, WriteError> {
+    let p = check_path(p, s)?;
+    // only the articles, not the assets or the other files of the roots
+    if s.get_by_path(&p).is_none() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "unknown article").into());
+    }
+
+    let _w = s.lock_files();
+    let current = article_writer::etag(&p)?;
+    if !etag_matches(if_match, &current) {
+        return Err(WriteError::Conflict(current));
+    }
+    article_writer::write_atomic(&p, content.as_bytes())?;
+
+    Ok(article_writer::etag(&p)?)
+}
+
+// a comma separated list of etags (RFC 7232), weak ones compared by their value
+fn etag_matches(if_match: &str, current: &str) -> bool {
+    if_match.trim() == "*"
+        || if_match
+            .split(',')
+            .map(|t| t.trim())
+            .map(|t| t.strip_prefix("W/").unwrap_or(t))
+            .any(|t| t == current)
+}
+
+// the tags listed in `from` become `to` in every article carrying them,
+// the changed files are returned (nothing is written in dry-run mode)
+pub fn rename_tags(
+    from: &[String],
+    to: &str,
+    dry_run: bool,
+    s: &Store,
+) -> io::Result<Vec<PathBuf>> {
+    if to.trim().is_empty() || from.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a tag is missing",
+        ));
+    }
+    // looked for as they were indexed, the headers are matched the same way
+    let mut paths: Vec<PathBuf> = from
+        .iter()
+        .flat_map(|t| s.get_by_tag(&s.normalize_tag(t)))
+        .map(|a| a.path)
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let rules = s.tag_rules();
+    let _w = s.lock_files();
+    let mut changed = vec![];
+    for p in paths {
+        let p = check_path(&p.to_string_lossy(), s)?;
+        let edit = TagEdit::Rename(from, to);
+        if let Some(content) = article_writer::edit_tags(&fs::read(&p)?, &edit, &rules) {
+            if !dry_run {
+                article_writer::write_atomic(&p, &content)?;
+            }
+            changed.push(p);
+        }
+    }
+    Ok(changed)
+}
+
+// the store is updated right away, the watcher echo then re-reads the same header
+pub fn edit_article_tags(p: &str, edit: &TagEdit, s: &Store) -> Result<TaggedArticle, WriteError> {
+    if let TagEdit::Add(t) | TagEdit::Remove(t) = edit {
+        if t.trim().is_empty() {
+            return Err(invalid_input("the tag is empty"));
+        }
+    }
+    let p = check_path(p, s)?;
+
+    let _w = s.lock_files();
+    if let Some(content) = article_writer::edit_tags(&fs::read(&p)?, edit, &s.tag_rules()) {
+        article_writer::write_atomic(&p, &content)?;
+    }
+    let m = metadata_handler::with_root(file_handler::get_metadata(&p)?, s)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "outside of the roots"))?;
+    s.insert(&m);
+    Ok(m)
+}
+
+pub const MAX_ASSET_SIZE: usize = 10 * 1024 * 1024;
+
+// the image is stored in the attachments folder (relative to the article's folder),
+// the link to embed in the article is returned
+pub fn upload_asset(
+    article: &str,
+    attachments: &str,
+    data: &[u8],
+    s: &Store,
+) -> Result<String, WriteError> {
+    if data.len() > MAX_ASSET_SIZE {
+        return Err(WriteError::TooLarge);
+    }
+    let name = article_writer::asset_name(data).ok_or(WriteError::UnsupportedType)?;
+    if Path::new(attachments)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(invalid_input("the attachments folder must be relative"));
+    }
+
+    let article = check_path(article, s)?;
+    let dir = article
+        .parent()
+        .ok_or_else(|| invalid_input("not an article"))?
+        .join(attachments);
+    let dir = create_dir_in_roots(&dir, s)?;
+
+    let p = dir.join(&name);
+    let _w = s.lock_files();
+    if !p.exists() {
+        article_writer::write_atomic(&p, data)?;
+    }
+
+    let link: Vec<String> = Path::new(attachments)
+        .join(name)
+        .iter()
+        .map(|c| c.to_string_lossy().to_string())
+        .collect();
+    Ok(link.join("/"))
+}
+
+fn invalid_input(msg: &str) -> WriteError {
+    WriteError::Io(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+// symlinks are resolved before checking the path is inside the roots
+fn check_path(p: &str, s: &Store) -> io::Result<PathBuf> {
+    let real = fs::canonicalize(p)?;
+    if !s.is_allowed(&real) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "outside of the roots",
+        ));
+    }
+    Ok(real)
+}
+
+// the folders are created once the deepest existing one is known to be in the roots :
+// through a symlink, they could be made anywhere before being refused
+fn create_dir_in_roots(dir: &Path, s: &Store) -> io::Result<PathBuf> {
+    let existing = dir
+        .ancestors()
+        .find(|a| a.exists())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such folder"))?;
+    check_path(&existing.to_string_lossy(), s)?;
+    fs::create_dir_all(dir)?;
+    check_path(&dir.to_string_lossy(), s)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::{Op, TaggedArticle};
+    use crate::domain::TagRules;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::time::{Duration, Instant};
+    use tempfile::tempdir;
 
     // helpers
     fn new_comb(op: Op, q1: Query, q2: Query) -> Query {
         Query::Comb(op, Box::new(q1), Box::new(q2))
     }
 
+    fn path(i: u8) -> PathBuf {
+        format!("path_{}", i).into()
+    }
     fn title(i: u8) -> String {
         format!("title_{}", i)
     }
@@ -81,10 +815,10 @@ mod tests {
     #[test]
     fn search_sing() -> std::io::Result<()> {
         let s = &Store::new();
-        let art0 = ArtRef::new(PathBuf::new(), &title(0));
-        let art1 = ArtRef::new(PathBuf::new(), &title(1));
-        let m0 = &TaggedArticle::new_from_art(&art0, &vec![tag(0)]);
-        let m1 = &TaggedArticle::new_from_art(&art1, &vec![tag(1)]);
+        let art0 = ArtRef::new(path(0), &title(0));
+        let art1 = ArtRef::new(path(1), &title(1));
+        let m0 = &TaggedArticle::new_from_art(&art0, &[tag(0)]);
+        let m1 = &TaggedArticle::new_from_art(&art1, &[tag(1)]);
         s.insert(m0);
         s.insert(m1);
 
@@ -98,23 +832,21 @@ mod tests {
     #[test]
     fn search_comb() -> std::io::Result<()> {
         let s = &Store::new();
-        let m0 = TaggedArticle::new(PathBuf::new(), &title(0), &vec![tag(0)]);
-        let m1 = TaggedArticle::new(PathBuf::new(), &title(1), &vec![tag(1)]);
+        let m0 = TaggedArticle::new(path(0), &title(0), &[tag(0)]);
+        let m1 = TaggedArticle::new(path(1), &title(1), &[tag(1)]);
 
-        let art2 = ArtRef::new(PathBuf::new(), &title(2));
-        let m2 = TaggedArticle::new_from_art(&art2, &vec![tag(0), tag(1)]);
+        let art2 = ArtRef::new(path(2), &title(2));
+        let m2 = TaggedArticle::new_from_art(&art2, &[tag(0), tag(1)]);
         s.insert(&m0);
         s.insert(&m1);
         s.insert(&m2);
 
-        assert_eq!(
-            vec![m0.clone(), m1.clone(), m2.clone()].sort(),
-            search_by_tag(
-                &new_comb(Op::Or, Query::Sing(tag(0)), Query::Sing(tag(1))),
-                s
-            )
-            .sort()
+        let mut found = search_by_tag(
+            &new_comb(Op::Or, Query::Sing(tag(0)), Query::Sing(tag(1))),
+            s,
         );
+        found.sort();
+        assert_eq!(vec![m0.art, m1.art, m2.art], found);
 
         assert_eq!(
             vec![art2],
@@ -125,4 +857,723 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn read_only_inside_roots() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().join("root");
+        fs::create_dir(&root)?;
+        fs::write(root.join("in.png"), "in")?;
+        fs::write(dir.path().join("out.png"), "out")?;
+        std::os::unix::fs::symlink(dir.path().join("out.png"), root.join("link.png"))?;
+
+        let s = Store::new();
+        s.add_root(&fs::canonicalize(&root)?, "root");
+
+        let path = |name: &str| root.join(name).to_string_lossy().to_string();
+        assert_eq!(b"in".to_vec(), fs::read(get_file(&path("in.png"), &s)?)?);
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            get_file(&path("../out.png"), &s).unwrap_err().kind()
+        );
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            get_file(&path("link.png"), &s).unwrap_err().kind()
+        );
+        Ok(())
+    }
+
+    fn random_article(rng: &mut StdRng, i: usize) -> String {
+        let tags: Vec<String> = (0..4)
+            .filter(|_| rng.gen_bool(0.5))
+            .map(|t| format!("- tag_{}\n", t))
+            .collect();
+
+        // some files end up with an invalid header
+        if rng.gen_bool(0.1) {
+            return format!("---\ntags:\n{}---\nno title\n", tags.concat());
+        }
+        format!(
+            "---\ntitle: title_{}\ntags:\n{}---\ncontent\n",
+            i,
+            tags.concat()
+        )
+    }
+
+    type Snapshot = (Vec<TaggedArticle>, Vec<(String, Vec<ArtRef>)>);
+
+    fn snapshot(s: &Store) -> Snapshot {
+        let mut arts = s.get_all_articles();
+        arts.sort();
+
+        let mut tags: Vec<(String, Vec<ArtRef>)> = s
+            .get_all_tags()
+            .into_iter()
+            .map(|t| {
+                let mut by_tag = s.get_by_tag(&t);
+                by_tag.sort();
+                (t, by_tag)
+            })
+            .collect();
+        tags.sort();
+
+        (arts, tags)
+    }
+
+    fn start(root: &Path) -> Arc<Store> {
+        start_roots(vec![Root::new(root.to_path_buf())])
+    }
+
+    fn start_roots(roots: Vec<Root>) -> Arc<Store> {
+        index(Store::new(), &roots, SymlinkPolicy::Follow, 200)
+            .unwrap()
+            .store()
+    }
+
+    fn wait_until(ok: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !ok() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        ok()
+    }
+
+    fn wait_for(s: &Store, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while s.get_all_articles().len() < count && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn several_roots() -> std::io::Result<()> {
+        let notes = tempdir()?;
+        let wiki = tempdir()?;
+        fs::write(
+            notes.path().join("a.md"),
+            "---\ntitle: a\ntags:\n- rust\n---\n",
+        )?;
+        fs::write(
+            wiki.path().join("b.md"),
+            "---\ntitle: b\ntags:\n- rust\n---\n",
+        )?;
+
+        let s = &start_roots(vec![
+            Root {
+                name: "notes".into(),
+                path: notes.path().to_path_buf(),
+            },
+            Root {
+                name: "wiki".into(),
+                path: wiki.path().to_path_buf(),
+            },
+        ]);
+        wait_for(s, 2);
+
+        assert_eq!(
+            vec!["notes".to_string(), "wiki".to_string()],
+            s.get_all_roots()
+        );
+
+        let in_wiki = search_by_tag(
+            &new_comb(
+                Op::And,
+                Query::Sing("rust".into()),
+                Query::Root("wiki".into()),
+            ),
+            s,
+        );
+        assert_eq!(1, in_wiki.len());
+        assert_eq!("b", in_wiki[0].title);
+        assert_eq!("wiki", in_wiki[0].root);
+
+        let in_notes = filter_by_root(s.get_by_tag("rust"), &Some("notes".into()));
+        assert_eq!(1, in_notes.len());
+        assert_eq!("a", in_notes[0].title);
+
+        let same_name = |p: &Path| Root {
+            name: "notes".into(),
+            path: p.to_path_buf(),
+        };
+        let roots = [same_name(notes.path()), same_name(wiki.path())];
+        assert!(index(Store::new(), &roots, SymlinkPolicy::Follow, 200).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn write_articles() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join("a.md"),
+            "---\ntitle: a\ntags:\n- rust\n---\n",
+        )?;
+        let s = &start(dir.path());
+        wait_for(s, 1);
+
+        let new = NewArticle {
+            title: "My note".into(),
+            tags: vec!["rust".into(), "elm".into()],
+            body: "hello\n".into(),
+            root: None,
+            path: None,
+        };
+        let (art, etag) = create_article(&new, s).unwrap();
+        assert_eq!("my-note", art.id);
+        let p = art.path;
+        wait_for(s, 2); // through the watcher
+        assert_eq!(1, s.get_by_tag("elm").len());
+
+        // already there
+        assert!(matches!(
+            create_article(&new, s),
+            Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists
+        ));
+        // another file, the same id
+        let same_id = NewArticle {
+            path: Some("my-note.markdown".into()),
+            ..new.clone()
+        };
+        assert!(matches!(
+            create_article(&same_id, s),
+            Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists
+        ));
+        assert!(!dir.path().join("my-note.markdown").exists());
+        // outside the root
+        let outside = NewArticle {
+            path: Some("../out.md".into()),
+            ..new
+        };
+        assert!(matches!(
+            create_article(&outside, s),
+            Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::InvalidInput
+        ));
+
+        let p = p.to_string_lossy();
+        let content = "---\ntitle: My note\ntags:\n- go\n---\n";
+        let new_etag = replace_article(&p, content, &etag, s).unwrap();
+        assert_eq!(content, fs::read_to_string(get_file(&p, s)?)?);
+        // edited with an outdated etag
+        assert!(matches!(
+            replace_article(&p, "lost", &etag, s),
+            Err(WriteError::Conflict(current)) if current == new_etag
+        ));
+        // one of several etags
+        let listed = format!("{}, W/{}", etag, new_etag);
+        assert!(replace_article(&p, content, &listed, s).is_ok());
+        // not an article
+        let asset = dir.path().join("knowledge.toml");
+        fs::write(&asset, "")?;
+        assert!(matches!(
+            replace_article(&asset.to_string_lossy(), "lost", "*", s),
+            Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+        assert_eq!("", fs::read_to_string(&asset)?);
+        Ok(())
+    }
+
+    #[test]
+    fn no_folders_outside_the_roots() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let elsewhere = tempdir()?;
+        std::os::unix::fs::symlink(elsewhere.path(), dir.path().join("away"))?;
+        let s = &scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Ignore,
+            Rules::default(),
+        );
+
+        let new = NewArticle {
+            title: "x".into(),
+            tags: vec![],
+            body: String::new(),
+            root: None,
+            path: Some("away/new/x.md".into()),
+        };
+        assert!(matches!(
+            create_article(&new, s),
+            Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied
+        ));
+        assert!(!elsewhere.path().join("new").exists());
+
+        let article = dir.path().join("a.md");
+        fs::write(&article, "---\ntitle: a\n---\n")?;
+        assert!(matches!(
+            upload_asset(&article.to_string_lossy(), "away/img", b"\x89PNG\r\n\x1a\n", s),
+            Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied
+        ));
+        assert!(!elsewhere.path().join("img").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn edit_tags_of_an_article() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let p = dir.path().join("a.md");
+        fs::write(&p, "---\ntitle: a\ntags:\n- rust\n---\n")?;
+        let s = &start(dir.path());
+        wait_for(s, 1);
+        let p = fs::canonicalize(p)?.to_string_lossy().to_string();
+
+        // applied at once
+        edit_article_tags(&p, &TagEdit::Add("elm"), s).unwrap();
+        assert_eq!(1, s.get_by_tag("elm").len());
+        edit_article_tags(&p, &TagEdit::Remove("rust"), s).unwrap();
+        assert!(s.get_by_tag("rust").is_empty());
+
+        // the echoes change nothing
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(1, s.get_by_tag("elm").len());
+        assert!(s.get_by_tag("rust").is_empty());
+        assert_eq!(vec!["elm".to_string()], s.get_all_articles()[0].tags);
+        Ok(())
+    }
+
+    #[test]
+    fn render_with_links() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("sub"))?;
+        fs::write(dir.path().join("b.md"), "---\ntitle: b\nid: bee\n---\n")?;
+        fs::write(
+            dir.path().join("sub").join("a.md"),
+            "---\ntitle: a\n---\n[b](../b.md#x) ![i](my%20img.png) [c](c.md)\n",
+        )?;
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+        let a = dir.path().join("sub").join("a.md");
+
+        let html = render_article(&a.to_string_lossy(), "/api", &s)?;
+        assert!(html.contains("href=\"/api/articles/bee/html#x\""));
+        assert!(html.contains("src=\"/api/assets/sub/my%20img.png\""));
+        // not an article (yet)
+        assert!(html.contains("href=\"/api/assets/sub/c.md\""));
+        Ok(())
+    }
+
+    #[test]
+    fn links_with_anchors() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("b.md"), "---\ntitle: b\n---\n## Set up\n")?;
+        fs::write(
+            dir.path().join("a.md"),
+            "---\ntitle: a\n---\n# Intro\n[1](b.md#set-up) [2](b.md#nope) [3](#intro) [4](c.md) [5](https://x.org)\n",
+        )?;
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+
+        let links = article_links(&dir.path().join("a.md").to_string_lossy(), &s)?;
+        let summary: Vec<(&str, Option<&str>, bool)> = links
+            .iter()
+            .map(|l| (l.dest.as_str(), l.id.as_deref(), l.valid))
+            .collect();
+        assert_eq!(
+            vec![
+                ("b.md#set-up", Some("b"), true),
+                ("b.md#nope", Some("b"), false),
+                ("#intro", Some("a"), true),
+                ("c.md", None, false),
+            ],
+            summary
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn links_follow_the_files() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a.md");
+        fs::write(&a, "---\ntitle: a\n---\n[later](b.md#later)\n")?;
+        fs::write(dir.path().join("b.md"), "---\ntitle: b\n---\n")?;
+        fs::create_dir(dir.path().join("sub"))?;
+        let s = &start(dir.path());
+        wait_for(s, 2);
+        let valid = |p: &Path| -> std::io::Result<bool> {
+            Ok(article_links(&p.to_string_lossy(), s)?[0].valid)
+        };
+        let eventually = |p: &Path, expected: bool| -> std::io::Result<bool> {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while valid(p)? != expected && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+            valid(p)
+        };
+        assert!(!valid(&a)?);
+
+        // the anchor appears
+        fs::write(dir.path().join("b.md"), "---\ntitle: b\n---\n## Later\n")?;
+        assert!(eventually(&a, true)?);
+
+        // moved away from b
+        let moved = fs::canonicalize(dir.path())?.join("sub").join("a.md");
+        fs::rename(&a, &moved)?;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while s.get_by_path(&moved).is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(s.get_by_path(&moved).is_some());
+        assert!(!eventually(&moved, false)?);
+        Ok(())
+    }
+
+    #[test]
+    fn wiki_links() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("sub"))?;
+        fs::write(
+            dir.path().join("sub").join("rust.md"),
+            "---\ntitle: The Rust language\naliases: [rustlang]\n---\n## Ownership\n",
+        )?;
+        fs::write(dir.path().join("go.md"), "---\ntitle: Go\n---\n")?;
+        fs::write(dir.path().join("go2.md"), "---\ntitle: go\n---\n")?;
+        fs::write(
+            dir.path().join("a.md"),
+            "---\ntitle: a\n---\n[[the rust language#Ownership|own]] [[RustLang]] [[sub/rust]] [[go]] [[nope]]\n",
+        )?;
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+        let a = dir.path().join("a.md");
+
+        let links = article_links(&a.to_string_lossy(), &s)?;
+        let summary: Vec<(&str, Option<&str>, bool, usize)> = links
+            .iter()
+            .map(|l| {
+                (
+                    l.dest.as_str(),
+                    l.id.as_deref(),
+                    l.valid,
+                    l.candidates.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("[[the rust language#Ownership]]", Some("sub/rust"), true, 0),
+                ("[[RustLang]]", Some("sub/rust"), true, 0),
+                ("[[sub/rust]]", Some("sub/rust"), true, 0),
+                // ambiguous
+                ("[[go]]", None, false, 2),
+                ("[[nope]]", None, false, 0),
+            ],
+            summary
+        );
+        assert_eq!(vec!["go", "go2"], links[3].candidates);
+
+        let html = render_article(&a.to_string_lossy(), "/api", &s)?;
+        assert!(html.contains("<a href=\"/api/articles/sub/rust/html#ownership\">own</a>"));
+        assert!(html.contains("<a href=\"/api/articles/go/html\">go</a>"));
+        assert!(html.contains("<a href=\"#\">nope</a>"));
+        Ok(())
+    }
+
+    #[test]
+    fn related() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let write = |name: &str, tags: &str, body: &str| {
+            fs::write(
+                dir.path().join(name),
+                format!("---\ntitle: {}\ntags: [{}]\n---\n{}\n", name, tags, body),
+            )
+        };
+        write("a.md", "rust", "ownership, borrow checker and lifetimes")?;
+        write("b.md", "rust", "the borrow checker errors")?;
+        write("c.md", "garden", "tomatoes, see [[a]]")?;
+        write("d.md", "garden", "tomatoes and basil")?;
+        let s = &start(dir.path());
+        wait_for(s, 4);
+
+        let a = dir.path().canonicalize()?.join("a.md");
+        let ids = || -> Vec<String> {
+            s.related_articles(&a, 10)
+                .into_iter()
+                .map(|r| r.art.id)
+                .collect()
+        };
+        // the texts follow the articles in the store
+        let eventually = |expected: Vec<&str>| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while ids() != expected && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+            assert_eq!(expected, ids());
+        };
+        eventually(vec!["b", "c"]);
+        let c = &s.related_articles(&a, 10)[1];
+        assert_eq!((0.0, 0.0, 1.0), (c.text, c.tags, c.links));
+
+        write(
+            "d.md",
+            "rust",
+            "ownership and lifetimes, the borrow checker",
+        )?;
+        eventually(vec!["d", "b", "c"]);
+        assert_eq!(2, related_articles(&a.to_string_lossy(), 2, s)?.len());
+        assert!(related_articles("nope.md", 2, s).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_with_rules() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("drafts"))?;
+        fs::write(
+            dir.path().join("a.md"),
+            "---\ntitle: a\ntags: [ML, Rust]\n---\n",
+        )?;
+        fs::write(
+            dir.path().join("drafts").join("b.md"),
+            "---\ntitle: b\ntags: [rust]\n---\n",
+        )?;
+        let rules = Rules {
+            ignore: crate::domain::IgnoreRules::new(&["drafts".into()]).unwrap(),
+            tags: crate::domain::TagRules {
+                lowercase: true,
+                synonyms: vec![("ml".to_string(), "machine-learning".to_string())]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+        };
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            rules,
+        );
+
+        assert_eq!(1, s.get_all_articles().len());
+        assert_eq!(
+            vec!["machine-learning", "rust"],
+            s.get_all_articles()[0].tags
+        );
+        // queries are normalized too
+        assert_eq!(1, search_by_tag(&Query::Sing("ML".into()), &s).len());
+        // not served either
+        let b = dir.path().join("drafts").join("b.md");
+        assert!(get_file(&b.to_string_lossy(), &s).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_queries() -> std::io::Result<()> {
+        let q: Query = "rust and (web or \"programming languages\") OR root:notes"
+            .parse()
+            .unwrap();
+        let comb = |op, a, b| Query::Comb(op, Box::new(a), Box::new(b));
+        let sing = |t: &str| Query::Sing(t.into());
+        assert_eq!(
+            comb(
+                Op::Or,
+                comb(
+                    Op::And,
+                    sing("rust"),
+                    comb(Op::Or, sing("web"), sing("programming languages"))
+                ),
+                Query::Root("notes".into())
+            ),
+            q
+        );
+        assert_eq!(Err("missing \"".to_string()), "\"rust".parse::<Query>());
+        assert!("rust and".parse::<Query>().is_err());
+        assert!("(rust".parse::<Query>().is_err());
+        assert!("rust web".parse::<Query>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn export_static_site() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let out = tempdir()?;
+        fs::create_dir_all(dir.path().join("sub"))?;
+        fs::write(
+            dir.path().join("sub").join("a.md"),
+            "---\ntitle: A\ntags: [public, rust]\n---\n## Intro\n[b](../b.md) [c](../c.md#x) [[C]] ![i](img.png) ![j](missing.png)\n",
+        )?;
+        fs::write(dir.path().join("sub").join("img.png"), "png")?;
+        fs::write(dir.path().join("sub").join("unused.png"), "png")?;
+        fs::write(
+            dir.path().join("b.md"),
+            "---\ntitle: B\ntags: [public]\n---\n",
+        )?;
+        fs::write(
+            dir.path().join("c.md"),
+            "---\ntitle: C\ntags: [public, private]\n---\n",
+        )?;
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+
+        let q: Query = "public and (rust or root:x or private)".parse().unwrap();
+        let summary = export_site(Some(&q), out.path(), &s)?;
+        assert_eq!(
+            ExportSummary {
+                articles: 2,
+                assets: 1
+            },
+            summary
+        );
+
+        let a = fs::read_to_string(out.path().join("articles/sub/a.html"))?;
+        assert!(a.contains("href=\"#\">b</a>")); // not exported
+        assert!(a.contains("href=\"../../articles/c.html#x\""));
+        assert!(a.contains("href=\"../../articles/c.html\">C</a>"));
+        assert!(a.contains("src=\"../../assets/sub/img.png\""));
+        assert!(a.contains("src=\"missing.png\""));
+        assert!(a.contains("href=\"../../tags/rust.html\""));
+        assert!(out.path().join("assets/sub/img.png").exists());
+        assert!(!out.path().join("assets/sub/unused.png").exists());
+        assert!(!out.path().join("articles/b.html").exists());
+
+        let public = fs::read_to_string(out.path().join("tags/public.html"))?;
+        assert!(public.contains("href=\"../articles/sub/a.html\">A</a>"));
+        let index = fs::read_to_string(out.path().join("index.html"))?;
+        assert!(index.contains("href=\"tags/private.html\">private</a>"));
+        let search = fs::read_to_string(out.path().join("search.json"))?;
+        assert!(search.contains("\"headings\":[\"Intro\"]"));
+        Ok(())
+    }
+
+    #[test]
+    fn upload_assets() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        let a = dir.path().join("sub").join("a.md");
+        fs::write(&a, "---\ntitle: a\n---\n")?;
+        let s = Store::new();
+        s.add_root(&fs::canonicalize(dir.path())?, "root");
+        let a = a.to_string_lossy();
+
+        let png = b"\x89PNG\r\n\x1a\nimage".to_vec();
+        let link = upload_asset(&a, "img/pasted", &png, &s).unwrap();
+        assert!(link.starts_with("img/pasted/") && link.ends_with(".png"));
+        assert_eq!(png, fs::read(dir.path().join("sub").join(&link))?);
+
+        // stored once
+        assert_eq!(link, upload_asset(&a, "img/pasted", &png, &s).unwrap());
+        assert_eq!(1, fs::read_dir(dir.path().join("sub/img/pasted"))?.count());
+
+        assert!(matches!(
+            upload_asset(&a, "img", b"<svg onload=alert(1)>", &s),
+            Err(WriteError::UnsupportedType)
+        ));
+        assert!(matches!(
+            upload_asset(&a, "../../out", &png, &s),
+            Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::InvalidInput
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rename_tags_in_files() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let a = "---\ntitle: a\ntags:\n- ml\n- rust\n---\nbody\n";
+        let b = "---\ntitle: b\ntags: [ML, machine-learning]\n---\n";
+        let c = "---\ntitle: c\ntags:\n- rust\n---\n";
+        for (name, content) in &[("a.md", a), ("b.md", b), ("c.md", c)] {
+            fs::write(dir.path().join(name), content)?;
+        }
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+        assert_eq!(3, s.get_all_articles().len());
+
+        let from = vec!["ml".to_string(), "ML".to_string()];
+        let names = |pp: Vec<PathBuf>| -> Vec<String> {
+            pp.iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into())
+                .collect()
+        };
+        assert_eq!(
+            vec!["a.md", "b.md"],
+            names(rename_tags(&from, "machine-learning", true, &s)?)
+        );
+        assert_eq!(a, fs::read_to_string(dir.path().join("a.md"))?); // dry run
+
+        rename_tags(&from, "machine-learning", false, &s)?;
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            Rules::default(),
+        );
+        assert_eq!(2, s.get_by_tag("machine-learning").len());
+        assert!(s.get_by_tag("ml").is_empty());
+        assert_eq!(c, fs::read_to_string(dir.path().join("c.md"))?);
+
+        // the tags are indexed lowercase : any case finds and edits them
+        let rules = Rules {
+            tags: TagRules {
+                lowercase: true,
+                ..TagRules::default()
+            },
+            ..Rules::default()
+        };
+        let s = scan(
+            &[Root::new(dir.path().to_path_buf())],
+            SymlinkPolicy::Follow,
+            rules,
+        );
+        rename_tags(&["RUST".to_string()], "Rust-lang", false, &s)?;
+        assert_eq!(
+            "---\ntitle: c\ntags:\n- Rust-lang\n---\n",
+            fs::read_to_string(dir.path().join("c.md"))?
+        );
+        let c = dir.path().join("c.md").to_string_lossy().to_string();
+        let m = edit_article_tags(&c, &TagEdit::Remove("RUST-LANG"), &s).unwrap();
+        assert!(m.tags.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn watched_store_matches_fresh_scan() -> std::io::Result<()> {
+        // fixed seeds : a failure replays
+        for seed in 0..3 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let dir = tempdir()?;
+            let watched = start(dir.path());
+
+            // the watcher is live once a change made after the traversal is seen
+            let ready = dir.path().join("ready.md");
+            fs::write(&ready, "---\ntitle: ready\ntags: [a]\n---\n")?;
+            assert!(wait_until(|| watched.get_by_tag("a").len() == 1));
+            fs::write(&ready, "---\ntitle: ready\ntags: [b]\n---\n")?;
+            assert!(wait_until(|| watched.get_by_tag("b").len() == 1));
+
+            // few names so that events on the same path pile up
+            let names: Vec<PathBuf> = (0..8)
+                .map(|i| dir.path().join(format!("note_{}.md", i)))
+                .collect();
+
+            for i in 0..300 {
+                let p = &names[rng.gen_range(0, names.len())];
+                match rng.gen_range(0, 4) {
+                    0 | 1 => fs::write(p, random_article(&mut rng, i))?,
+                    2 => {
+                        let _ = fs::rename(p, &names[rng.gen_range(0, names.len())]);
+                    }
+                    _ => {
+                        let _ = fs::remove_file(p);
+                    }
+                }
+            }
+
+            let fresh = scan(
+                &[Root::new(dir.path().to_path_buf())],
+                SymlinkPolicy::Follow,
+                Rules::default(),
+            );
+            let matches = wait_until(|| snapshot(&watched) == snapshot(&fresh));
+            assert!(matches, "seed {}", seed);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,85 @@
+// the library as another service embeds it, through its public api only
+use knowledge::{
+    index, parse_front_matter, scan, search_by_tag, DiskBackend, Query, Root, Rules, Store,
+    SymlinkPolicy,
+};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+fn write(dir: &Path, name: &str, tags: &str) -> std::io::Result<()> {
+    fs::write(
+        dir.join(name),
+        format!("---\ntitle: {}\ntags: [{}]\n---\n", name, tags),
+    )
+}
+
+fn wait_until(ok: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !ok() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+    ok()
+}
+
+#[test]
+fn front_matter() -> std::io::Result<()> {
+    let h = parse_front_matter("---\ntitle: A\ntags: [rust, web]\nid: a-1\n---\nbody\n")?;
+    assert_eq!("A", h.title);
+    assert_eq!(vec!["rust", "web"], h.tags);
+    assert_eq!(Some("a-1".to_string()), h.id);
+    assert!(parse_front_matter("no header").is_err());
+    Ok(())
+}
+
+#[test]
+fn scan_and_query() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    write(dir.path(), "a.md", "rust, web")?;
+    write(dir.path(), "b.md", "rust")?;
+    write(dir.path(), "c.md", "garden")?;
+
+    let s = scan(
+        &[Root::new(dir.path().to_path_buf())],
+        SymlinkPolicy::Follow,
+        Rules::default(),
+    );
+    assert_eq!(2, s.get_by_tag("rust").len());
+    assert_eq!("a.md", s.get_by_id("a").unwrap().art.title);
+
+    let q: Query = "rust and not_there or garden".parse().unwrap();
+    let mut found: Vec<String> = search_by_tag(&q, &s).into_iter().map(|a| a.id).collect();
+    found.sort();
+    assert_eq!(vec!["c"], found);
+    Ok(())
+}
+
+#[test]
+fn index_until_stopped() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let disk = tempdir()?;
+    write(dir.path(), "a.md", "rust")?;
+
+    let store = Store::with_backend(DiskBackend::open(disk.path())?);
+    let handle = index(
+        store,
+        &[Root::new(dir.path().to_path_buf())],
+        SymlinkPolicy::Follow,
+        50,
+    )?;
+    let s = handle.store();
+    assert!(wait_until(|| s.get_by_tag("rust").len() == 1));
+
+    // kept up to date with the files
+    write(dir.path(), "b.md", "rust")?;
+    assert!(wait_until(|| s.get_by_tag("rust").len() == 2));
+
+    handle.stop();
+    thread::sleep(Duration::from_millis(500));
+    write(dir.path(), "c.md", "rust")?;
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(2, s.get_by_tag("rust").len());
+    Ok(())
+}